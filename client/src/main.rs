@@ -1,17 +1,15 @@
-use std::fs;
 use std::io::{BufRead, BufReader, Write};
 use std::os::unix::net::UnixStream;
-use std::path::{Path, PathBuf};
-use std::str::FromStr;
+use std::path::Path;
 
-use rand::seq::{IndexedRandom, SliceRandom};
-use rand::rng;
-
-use common::Request;
+use common::{Command, PathOverrides, Paths, Request};
 
 fn main() {
-    if !Path::new(SOCKET_PATH).exists() {
-        eprintln!("Daemon is not found. Is it running?");
+    let args = std::env::args().collect::<Vec<String>>();
+    let paths = Paths::resolve(&PathOverrides::from_args(&args));
+
+    if !Path::new(&paths.socket).exists() {
+        eprintln!("Daemon is not found at {}. Is it running?", paths.socket);
         return;
     }
 
@@ -23,83 +21,35 @@ fn main() {
         return;
     }
 
-    let req = Request::from_args(std::env::args().skip(1).collect());
+    let req = Request::from_args(&std::env::args().skip(1).collect::<Vec<String>>());
     let serialized = serde_json::to_string(&req).unwrap();
-    let _ = send(&serialized);
+    let _ = send(
+        &paths.socket,
+        &serialized,
+        req.commands.contains(&Command::Subscribe),
+    );
 }
 
-fn send(message: &str) -> std::io::Result<()> {
-    let message = format!("{}\n", message);
+fn send(socket_path: &str, message: &str, follow: bool) -> std::io::Result<()> {
+    let message = format!("{message}\n");
 
-    let mut stream = UnixStream::connect(SOCKET_PATH)?;
+    let mut stream = UnixStream::connect(socket_path)?;
     let mut reader = BufReader::new(stream.try_clone()?);
     stream.write_all(message.as_bytes())?;
     let mut response = String::new();
     reader.read_line(&mut response)?;
-    println!("{response}");
-    Ok(())
-}
-
-fn is_dir(path: &str) -> bool {
-    fs::metadata(path)
-        .map(|meta| meta.is_dir())
-        .unwrap_or(false)
-}
-
-fn select_random(strings: Vec<String>) -> String {
-    let mut rng = rng();
-
-    if let Some(random_string) = strings.choose(&mut rng) {
-        random_string.to_string()
-    } else {
-        panic!("Directory is empty")
-    }
-}
-
-fn is_file_image(extension: &str) -> bool {
-    matches!(
-        extension.to_lowercase().as_str(),
-        "jpg" | "jpeg" | "webp" | "png" | "gif" | "bmp" | "tiff"
-    )
-}
-
-fn get_absolute_path(path: String) -> String {
-    let Ok(path) = PathBuf::from_str(&path);
-
-    return path
-        .canonicalize()
-        .unwrap_or_else(|_| path)
-        .to_string_lossy()
-        .to_string();
-}
+    println!("{}", response.trim_end());
 
-fn get_images_from_dir(dir: &str) -> Vec<String> {
-    let path = Path::new(dir);
-    let files = fs::read_dir(path).unwrap();
-    let mut res: Vec<String> = Vec::new();
-
-    for entry in files {
-        let entry = entry.unwrap();
-        let file_type = entry.file_type().unwrap();
-        if file_type.is_dir() {
-            res.extend(get_images_from_dir(&get_absolute_path(
-                entry.path().to_string_lossy().to_string(),
-            )))
-        } else if file_type.is_file() {
-            if let Some(extension) = entry.path().extension() {
-                if is_file_image(extension.to_str().unwrap_or("")) {
-                    res.push(get_absolute_path(
-                        entry.path().to_string_lossy().to_string(),
-                    ))
-                }
-            }
+    // events are streamed line by line until the daemon closes the connection
+    if follow {
+        for line in reader.lines() {
+            println!("{}", line?);
         }
     }
-    res
+    Ok(())
 }
 
-const SOCKET_PATH: &str = "/tmp/rpaper-daemon";
-const HELP_MESSAGE: &str = r#"+-----------------------------+-------------------------------------------------------+
+const HELP_MESSAGE: &str = r"+-----------------------------+-------------------------------------------------------+
 |                             |                                                       |
 |          argument           |                      description                      |
 |                             |                                                       |
@@ -115,6 +65,23 @@ const HELP_MESSAGE: &str = r#"+-----------------------------+-------------------
 | -C                          | cache colors                                          |
 |                             |                                                       |
 | --set-command <value>       | set different wallpaper set command                   |
+|                             |                                                       |
+| --fit <value>               | set how the image is placed on displays               |
+|                             |     possible values:                                  |
+|                             |     fill, contain, center, tile, stretch              |
+|                             |                                                       |
+| --background <value>        | set background of contain and center fit              |
+|                             |     possible values:                                  |
+|                             |     blur, palette, #rrggbb                            |
+|                             |                                                       |
+| --gravity <value>           | set which part of the image is kept by fill fit       |
+|                             |     possible values:                                  |
+|                             |     center, top, bottom, left, right, top-left,       |
+|                             |     top-right, bottom-left, bottom-right, smart       |
+|                             |                                                       |
+| --focal <value>             | keep this point of the image in the middle            |
+|                             |     possible values:                                  |
+|                             |     0.0-1.0X0.0-1.0                                   |
 +-----------------------------+-------------------------------------------------------+
 | --contrast <value>          | change image contrast                                 |
 |                             |                                                       |
@@ -133,6 +100,8 @@ const HELP_MESSAGE: &str = r#"+-----------------------------+-------------------
 | --displays <value>          | set displays wallpaper setted to params               |
 |                             |     example:                                          |
 |                             |     HDMI-A-1:1920:1080:0:0,DP-1:1080:1920:0:0         |
+|                             |     NAME:W:H:X:Y[:SCALE[:TRANSFORM[:BEZEL]]]          |
+|                             |     DP-1:3840:2160:-1080:0:2:90:20                    |
 |                             |                                                       |
 | --templates <value>         | set templates to be applied                           |
 |                             |     example:                                          |
@@ -167,13 +136,60 @@ const HELP_MESSAGE: &str = r#"+-----------------------------+-------------------
 +-----------------------------+-------------------------------------------------------+
 | -I <path/to/image>          | sends wallpaper to daemon                             |
 |                             |                                                       |
-| --cache                     | allow applying actions to all images from directory   |
-|                             | if -I argument is a directory                         |
+| -A                          | allow applying actions to all images from directory   |
+|                             | if -I argument is a directory,                        |
+|                             | runs as background job and responds with its id      |
+|                             |                                                       |
+| --independent               | set a separate image on every display,                |
+|                             | random images are picked if -I is a directory         |
+|                             |                                                       |
+| --per-display <value>       | set images of displays, path may be a directory       |
+|                             |     example:                                          |
+|                             |     HDMI-A-1=path;DP-1=anotherpath                    |
+|                             |                                                       |
+| --profile <value>           | switch to a display profile and set the wallpaper     |
+|                             | again, auto matches the connected displays            |
 +-----------------------------+-------------------------------------------------------+
-| --get-displays              | get loaded displays in json format                    |
+| --prev                      | set the previous wallpaper from the history           |
+|                             |                                                       |
+| --next                      | set the next wallpaper from the history               |
+|                             |                                                       |
+| --undo                      | set the previous wallpaper and forget the current one |
+|                             |                                                       |
+| --get-history               | get recently set wallpapers in json format            |
++-----------------------------+-------------------------------------------------------+
+| --rotate <source>           | change the wallpaper every interval,                  |
+|                             | with -T the colorscheme is applied as well            |
+|                             |     source is a directory, a playlist                 |
+|                             |     (.m3u, .m3u8 or .txt) or a list of images         |
+|                             |     example:                                          |
+|                             |     path;anotherpath;anotherpath                      |
+|                             |                                                       |
+| --interval <value>          | set how often the rotation changes the wallpaper      |
+|                             |     example:                                          |
+|                             |     90s, 15m, 2h, 1d (30m by default)                 |
+|                             |                                                       |
+| --rotate-order <value>      | set the order of the rotation                         |
+|                             |     possible values:                                  |
+|                             |     random, shuffle, name, mtime                      |
+|                             |                                                       |
+| --rotate-pause [id]         | pause rotation, all rotations without an id           |
+|                             |                                                       |
+| --rotate-resume [id]        | resume rotation                                       |
+|                             |                                                       |
+| --rotate-next [id]          | set the next image of the rotation                    |
+|                             |                                                       |
+| --rotate-prev [id]          | set the previous image of the rotation                |
+|                             |                                                       |
+| --rotate-stop [id]          | stop rotation                                         |
++-----------------------------+-------------------------------------------------------+
+| --get-displays              | get loaded displays and their profile in json format  |
 |                             |                                                       |
 | --get-current-scheme        | get current color scheme                              |
 |                             |                                                       |
+| --get-state                 | get current wallpapers, palette and applied templates |
+|                             | and rotations                                         |
+|                             |                                                       |
 | --get-templates             | get loaded templates in json format                   |
 |                             |                                                       |
 | --get-image-ops             | get loaded image operations in json format            |
@@ -182,10 +198,44 @@ const HELP_MESSAGE: &str = r#"+-----------------------------+-------------------
 |                             |                                                       |
 | --get-config                | get loaded config in json format                      |
 |                             |                                                       |
+| --validate-config           | check config file and get its errors and warnings     |
+|                             |                                                       |
 | --get-w-cache               | get cached images paths                               |
 |                             |                                                       |
 | --get-c-cache               | get color pallete of image                            |
-+-----------------------------+-------------------------------------------------------+"#;
++-----------------------------+-------------------------------------------------------+
+| --cache-stats               | get cached files of every image with their sizes      |
+|                             | and last use in json format                           |
+|                             |                                                       |
+| --cache-prune               | remove cache of deleted or changed images             |
+|                             |                                                       |
+| --cache-clear               | remove cache of the -I image or directory,            |
+|                             | of --cache-layout or of both                          |
+|                             |                                                       |
+| --cache-layout <value>      | a profile, a display or displays joined with +        |
+|                             |     example:                                          |
+|                             |     HDMI-A-1+DP-1                                     |
++-----------------------------+-------------------------------------------------------+
+| --jobs                      | list background jobs                                  |
+|                             |                                                       |
+| --job <id>                  | get progress of background job                        |
+|                             |                                                       |
+| --cancel <id>               | cancel background job                                 |
+|                             |                                                       |
+| --subscribe                 | keep connection open and print daemon events          |
+|                             | as newline-delimited json                             |
++-----------------------------+-------------------------------------------------------+
+| --shutdown                  | stop the daemon and remove its socket                 |
++-----------------------------+-------------------------------------------------------+
+| --socket <path>             | daemon socket, defaults to $RPAPER_SOCKET,            |
+|                             | socket_path config key, $XDG_RUNTIME_DIR/rpaper.sock  |
+|                             | or /tmp/rpaper-daemon-$UID                            |
+|                             |                                                       |
+| --config <path>             | config file used to look up socket_path               |
+|                             |                                                       |
+| --config-dir <path>         | config directory, defaults to $RPAPER_CONFIG_DIR,     |
+|                             | $XDG_CONFIG_HOME/rpaper or ~/.config/rpaper           |
++-----------------------------+-------------------------------------------------------+";
 
 // Socket calls
 // +------------------------------+-------------------------------------------------------+
@@ -1,45 +1,175 @@
 use serde_derive::{Deserialize, Serialize};
 use std::str::FromStr;
 
-#[derive(Clone, Serialize, Deserialize)]
+// `w` and `h` are the resolution of the display mode in physical pixels, before the transform,
+// `x` and `y` are its position in the logical layout, the way compositors report them
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct Display {
     pub name: String,
     pub w: u32,
     pub h: u32,
-    pub x: u32,
-    pub y: u32,
+    pub x: i32,
+    pub y: i32,
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    #[serde(default)]
+    pub transform: Transform,
+    // width of the frame around the screen in logical pixels, so spanned images line up across it
+    #[serde(default)]
+    pub bezel: u32,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Transform {
+    #[default]
+    #[serde(rename = "normal")]
+    Normal,
+    #[serde(rename = "90")]
+    Rotate90,
+    #[serde(rename = "180")]
+    Rotate180,
+    #[serde(rename = "270")]
+    Rotate270,
+    #[serde(rename = "flipped")]
+    Flipped,
+    #[serde(rename = "flipped-90")]
+    Flipped90,
+    #[serde(rename = "flipped-180")]
+    Flipped180,
+    #[serde(rename = "flipped-270")]
+    Flipped270,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+impl Transform {
+    #[must_use]
+    pub fn swaps_axes(self) -> bool {
+        matches!(
+            self,
+            Transform::Rotate90
+                | Transform::Rotate270
+                | Transform::Flipped90
+                | Transform::Flipped270
+        )
+    }
+
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Transform::Normal => "normal",
+            Transform::Rotate90 => "90",
+            Transform::Rotate180 => "180",
+            Transform::Rotate270 => "270",
+            Transform::Flipped => "flipped",
+            Transform::Flipped90 => "flipped-90",
+            Transform::Flipped180 => "flipped-180",
+            Transform::Flipped270 => "flipped-270",
+        }
+    }
+}
+
+impl FromStr for Transform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "normal" | "0" => Ok(Transform::Normal),
+            "90" => Ok(Transform::Rotate90),
+            "180" => Ok(Transform::Rotate180),
+            "270" => Ok(Transform::Rotate270),
+            "flipped" => Ok(Transform::Flipped),
+            "flipped-90" => Ok(Transform::Flipped90),
+            "flipped-180" => Ok(Transform::Flipped180),
+            "flipped-270" => Ok(Transform::Flipped270),
+            _ => Err(format!("unknown transform `{s}`")),
+        }
+    }
 }
 
 impl Display {
-    pub fn new(name: String, w: u32, h: u32, x: u32, y: u32) -> Self {
-        Display { name, w, h, x, y }
+    #[must_use]
+    pub fn new(name: String, w: u32, h: u32, x: i32, y: i32) -> Self {
+        Display {
+            name,
+            w,
+            h,
+            x,
+            y,
+            scale: default_scale(),
+            transform: Transform::default(),
+            bezel: 0,
+        }
+    }
+
+    // size of the image the display shows, with the transform applied
+    #[must_use]
+    pub fn physical_size(&self) -> (u32, u32) {
+        if self.transform.swaps_axes() {
+            (self.h, self.w)
+        } else {
+            (self.w, self.h)
+        }
+    }
+
+    // size the display takes in the layout
+    #[must_use]
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    pub fn logical_size(&self) -> (u32, u32) {
+        let (w, h) = self.physical_size();
+        let scale = if self.scale > 0.0 { self.scale } else { 1.0 };
+        (
+            (w as f32 / scale).round() as u32,
+            (h as f32 / scale).round() as u32,
+        )
     }
 }
 
+// NAME:W:H:X:Y[:SCALE[:TRANSFORM[:BEZEL]]]
 impl FromStr for Display {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut name: String = String::new();
-        let mut w: u32 = 0;
-        let mut h: u32 = 0;
-        let mut x: u32 = 0;
-        let mut y: u32 = 0;
-
-        if s.split(":").collect::<Vec<_>>().len() != 5 {
+        let params: Vec<&str> = s.split(':').collect();
+        if !(5..=8).contains(&params.len()) {
             return Err(String::from("Unable to parse string"));
         }
 
-        s.split(":").enumerate().for_each(|(i, param)| match i {
-            0 => name = String::from(param),
-            1 => w = param.parse().unwrap_or(0),
-            2 => h = param.parse().unwrap_or(0),
-            3 => x = param.parse().unwrap_or(0),
-            4 => y = param.parse().unwrap_or(0),
-            _ => {}
-        });
+        let number = |i: usize| {
+            params[i]
+                .parse::<i32>()
+                .map_err(|_| format!("`{}` is not a valid number", params[i]))
+        };
+        let mut display = Display::new(
+            params[0].to_string(),
+            number(1)?.try_into().map_err(|_| "width is negative")?,
+            number(2)?.try_into().map_err(|_| "height is negative")?,
+            number(3)?,
+            number(4)?,
+        );
 
-        Ok(Display::new(name, w, h, x, y))
+        if let Some(scale) = params.get(5) {
+            display.scale = scale
+                .parse()
+                .ok()
+                .filter(|scale: &f32| *scale > 0.0)
+                .ok_or_else(|| format!("`{scale}` is not a valid scale"))?;
+        }
+        if let Some(transform) = params.get(6) {
+            display.transform = transform.parse()?;
+        }
+        if let Some(bezel) = params.get(7) {
+            display.bezel = bezel
+                .parse()
+                .map_err(|_| format!("`{bezel}` is not a valid bezel"))?;
+        }
+
+        Ok(display)
     }
 }
-
@@ -0,0 +1,33 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::job::JobProgress;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    WallpaperSet {
+        display: String,
+        image: String,
+        path: String,
+    },
+    PaletteApplied {
+        image: String,
+        colors: Vec<String>,
+    },
+    TemplateWritten {
+        template: String,
+        path: String,
+        exec_after: Vec<String>,
+    },
+    ExecAfterFinished {
+        template: String,
+        command: String,
+        status: Option<i32>,
+    },
+    ConfigReloaded,
+    // the layout of displays changed, `profile` is the display profile in use
+    DisplaysChanged {
+        profile: Option<String>,
+    },
+    JobProgress(JobProgress),
+}
@@ -0,0 +1,23 @@
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Finished,
+    Cancelled,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct JobProgress {
+    pub id: u64,
+    pub status: JobStatus,
+    pub source: String,
+    pub done: usize,
+    pub total: usize,
+    pub current: Option<String>,
+    pub errors: Vec<String>,
+    pub queued_at: u128,
+    pub finished_at: Option<u128>,
+}
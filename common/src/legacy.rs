@@ -0,0 +1,101 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::display::Display;
+use crate::protocol::{Command, Overrides, Request, PROTOCOL_VERSION};
+
+// Flat request format used before the protocol was versioned.
+// Still accepted by the daemon for one release, will be removed afterwards.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct LegacyRequest {
+    pub image: Option<String>,
+    pub affect_all: bool,
+    pub w_set: bool,
+    pub w_cache: bool,
+    pub c_set: bool,
+    pub c_cache: bool,
+    pub displays: Option<Vec<Display>>,
+    pub templates: Option<Vec<String>>,
+    pub resize_alg: Option<String>,
+    pub set_command: Option<String>,
+    pub contrast: Option<f32>,
+    pub brightness: Option<i32>,
+    pub hue: Option<i32>,
+    pub blur: Option<f32>,
+    pub invert: Option<bool>,
+    pub flip_h: Option<bool>,
+    pub flip_v: Option<bool>,
+    pub rwal_thumb: Option<String>,
+    pub rwal_clamp: Option<String>,
+    pub rwal_accent: Option<u32>,
+    pub rwal_order: Option<String>,
+    pub get_current_colorscheme: bool,
+    pub get_config: bool,
+    pub get_w_cache: bool,
+    pub get_c_cache: bool,
+    pub w_cache_on_miss: bool,
+    pub c_cache_on_miss: bool,
+}
+
+impl From<LegacyRequest> for Request {
+    fn from(legacy: LegacyRequest) -> Self {
+        let mut commands = Vec::new();
+
+        if legacy.get_config {
+            commands.push(Command::GetConfig);
+        }
+        if legacy.get_current_colorscheme {
+            commands.push(Command::GetCurrentScheme);
+        }
+        if legacy.get_w_cache {
+            commands.push(Command::GetWallpaperCache);
+        }
+        if legacy.get_c_cache {
+            commands.push(Command::GetColorCache);
+        }
+        if legacy.c_set {
+            commands.push(Command::SetColors);
+        } else if legacy.c_cache {
+            commands.push(Command::CacheColors);
+        }
+        if legacy.w_set {
+            commands.push(Command::SetWallpaper);
+        } else if legacy.w_cache {
+            commands.push(Command::CacheWallpaper);
+        }
+
+        Request {
+            version: PROTOCOL_VERSION,
+            image: legacy.image,
+            affect_all: legacy.affect_all,
+            per_display: None,
+            independent: false,
+            profile: None,
+            rotation: None,
+            cache_layout: None,
+            overrides: Overrides {
+                displays: legacy.displays,
+                templates: legacy.templates,
+                resize_alg: legacy.resize_alg,
+                set_command: legacy.set_command,
+                fit: None,
+                background: None,
+                gravity: None,
+                focal_point: None,
+                contrast: legacy.contrast,
+                brightness: legacy.brightness,
+                hue: legacy.hue,
+                blur: legacy.blur,
+                invert: legacy.invert,
+                flip_h: legacy.flip_h,
+                flip_v: legacy.flip_v,
+                rwal_thumb: legacy.rwal_thumb,
+                rwal_clamp: legacy.rwal_clamp,
+                rwal_accent: legacy.rwal_accent,
+                rwal_order: legacy.rwal_order,
+            },
+            commands,
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_derive::Deserialize;
+
+pub const SOCKET_ENV: &str = "RPAPER_SOCKET";
+pub const CONFIG_ENV: &str = "RPAPER_CONFIG";
+pub const CONFIG_DIR_ENV: &str = "RPAPER_CONFIG_DIR";
+pub const CACHE_DIR_ENV: &str = "RPAPER_CACHE_DIR";
+
+const CONFIG_NAMES: [&str; 4] = ["config.toml", "config.yaml", "config.yml", "config.json"];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    #[must_use]
+    pub fn from_path(path: &str) -> Self {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => ConfigFormat::Toml,
+            Some("yaml" | "yml") => ConfigFormat::Yaml,
+            _ => ConfigFormat::Json,
+        }
+    }
+}
+
+// values passed on the command line, they take precedence over everything else
+#[derive(Clone, Default)]
+pub struct PathOverrides {
+    pub socket: Option<String>,
+    pub config: Option<String>,
+    pub config_dir: Option<String>,
+    pub cache_dir: Option<String>,
+}
+
+impl PathOverrides {
+    #[must_use]
+    pub fn from_args(args: &[String]) -> Self {
+        let value = |flag: &str| {
+            args.iter()
+                .position(|arg| arg == flag)
+                .and_then(|i| args.get(i + 1))
+                .map(|value| expand_home(value))
+        };
+
+        PathOverrides {
+            socket: value("--socket"),
+            config: value("--config"),
+            config_dir: value("--config-dir"),
+            cache_dir: value("--cache-dir"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Paths {
+    pub socket: String,
+    pub config: Option<String>,
+    pub config_dir: String,
+    pub cache_dir: String,
+}
+
+// the path keys of the config file
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct PathKeys {
+    pub socket_path: Option<String>,
+    pub cache_dir: Option<String>,
+}
+
+impl Paths {
+    // the client reads the config keys with a scanner, see read_path_keys
+    #[must_use]
+    pub fn resolve(overrides: &PathOverrides) -> Self {
+        Self::resolve_with(overrides, read_path_keys)
+    }
+
+    // every path is taken from the first source which sets it:
+    // flag, environment variable, config key, xdg directory, fallback
+    // read_keys gets the path of the config file
+    #[must_use]
+    pub fn resolve_with(
+        overrides: &PathOverrides,
+        read_keys: impl FnOnce(&str) -> PathKeys,
+    ) -> Self {
+        let config_dir = overrides
+            .config_dir
+            .clone()
+            .or_else(|| env_path(CONFIG_DIR_ENV))
+            .or_else(|| env_path("XDG_CONFIG_HOME").map(|dir| format!("{dir}/rpaper")))
+            .unwrap_or_else(|| expand_home("~/.config/rpaper"));
+
+        let mut paths = Paths {
+            socket: String::new(),
+            config: overrides.config.clone().or_else(|| env_path(CONFIG_ENV)),
+            config_dir,
+            cache_dir: String::new(),
+        };
+
+        let keys = read_keys(&paths.config_path());
+
+        paths.socket = overrides
+            .socket
+            .clone()
+            .or_else(|| env_path(SOCKET_ENV))
+            .or(keys.socket_path)
+            .or_else(|| env_path("XDG_RUNTIME_DIR").map(|dir| format!("{dir}/rpaper.sock")))
+            .unwrap_or_else(|| format!("/tmp/rpaper-daemon-{}", current_uid()));
+
+        paths.cache_dir = overrides
+            .cache_dir
+            .clone()
+            .or_else(|| env_path(CACHE_DIR_ENV))
+            .or(keys.cache_dir)
+            .or_else(|| env_path("XDG_CACHE_HOME").map(|dir| format!("{dir}/rpaper")))
+            .unwrap_or_else(|| expand_home("~/.cache/rpaper"));
+
+        paths
+    }
+
+    #[must_use]
+    pub fn config_candidates(&self) -> Vec<String> {
+        if let Some(path) = &self.config {
+            return vec![path.clone()];
+        }
+        CONFIG_NAMES
+            .iter()
+            .map(|name| format!("{}/{name}", self.config_dir))
+            .collect()
+    }
+
+    // the first existing candidate is used, json is assumed until any of them is created
+    #[must_use]
+    pub fn config_path(&self) -> String {
+        let candidates = self.config_candidates();
+        candidates
+            .iter()
+            .find(|path| Path::new(path).exists())
+            .unwrap_or(&candidates[candidates.len() - 1])
+            .clone()
+    }
+
+    #[must_use]
+    pub fn wallpapers_dir(&self) -> String {
+        format!("{}/wallpapers", self.cache_dir)
+    }
+
+    #[must_use]
+    pub fn colors_dir(&self) -> String {
+        format!("{}/rwal", self.cache_dir)
+    }
+
+    #[must_use]
+    pub fn colors_path(&self) -> String {
+        format!("{}/rwal/colors", self.cache_dir)
+    }
+
+    #[must_use]
+    pub fn state_path(&self) -> String {
+        format!("{}/state.json", self.cache_dir)
+    }
+
+    #[must_use]
+    pub fn history_path(&self) -> String {
+        format!("{}/history.json", self.cache_dir)
+    }
+
+    #[must_use]
+    pub fn sources_path(&self) -> String {
+        format!("{}/sources.json", self.cache_dir)
+    }
+
+    #[must_use]
+    pub fn manifest_path(&self) -> String {
+        format!("{}/manifest.json", self.cache_dir)
+    }
+}
+
+fn read_path_keys(config_path: &str) -> PathKeys {
+    let Ok(caption) = fs::read_to_string(config_path) else {
+        return PathKeys::default();
+    };
+
+    let keys = match ConfigFormat::from_path(config_path) {
+        ConfigFormat::Json => serde_json::from_str::<PathKeys>(&caption).unwrap_or_default(),
+        format => scan_path_keys(&caption, format),
+    };
+
+    PathKeys {
+        socket_path: keys.socket_path.map(|path| expand_home(&path)),
+        cache_dir: keys.cache_dir.map(|path| expand_home(&path)),
+    }
+}
+
+// only two top-level strings are needed, toml and yaml are scanned for them instead of parsed
+// so the client does not depend on the config parsers of the daemon, the daemon parses the
+// whole config instead. escapes and multi-line strings are not supported
+fn scan_path_keys(caption: &str, format: ConfigFormat) -> PathKeys {
+    let separator = if format == ConfigFormat::Toml {
+        '='
+    } else {
+        ':'
+    };
+    let mut keys = PathKeys::default();
+    for line in caption.lines() {
+        // toml tables and nested yaml mappings end the top level
+        if format == ConfigFormat::Toml && line.trim_start().starts_with('[') {
+            break;
+        }
+        if format == ConfigFormat::Yaml && line.starts_with(char::is_whitespace) {
+            continue;
+        }
+        let Some((key, value)) = line.split_once(separator) else {
+            continue;
+        };
+        let value = scalar_value(value);
+        match key.trim().trim_matches(['"', '\'']) {
+            "socket_path" => keys.socket_path = value,
+            "cache_dir" => keys.cache_dir = value,
+            _ => {}
+        }
+    }
+    keys
+}
+
+// a quoted string or a plain yaml scalar up to its comment, a yaml null is no value
+fn scalar_value(value: &str) -> Option<String> {
+    let value = value.trim();
+    let value = match value.chars().next()? {
+        quote @ ('"' | '\'') => value[1..].split(quote).next()?,
+        _ => match value.split(" #").next()?.trim() {
+            "~" | "null" | "Null" | "NULL" => return None,
+            value => value,
+        },
+    };
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+fn current_uid() -> u32 {
+    unsafe { libc::getuid() }
+}
+
+fn env_path(name: &str) -> Option<String> {
+    env::var(name)
+        .ok()
+        .filter(|value| !value.is_empty())
+        .map(|value| expand_home(&value))
+}
+
+fn expand_home(path: &str) -> String {
+    let Some(stripped) = path.strip_prefix('~') else {
+        return path.to_string();
+    };
+
+    match env::var_os("HOME") {
+        Some(home) => PathBuf::from(home)
+            .join(stripped.trim_start_matches('/'))
+            .to_string_lossy()
+            .to_string(),
+        None => path.to_string(),
+    }
+}
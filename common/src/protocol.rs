@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::display::Display;
+use crate::rotation::{parse_interval, Rotation, DEFAULT_ROTATION_INTERVAL};
+
+// bumped whenever commands, request fields or responses change, so a client and a daemon of
+// different releases refuse each other instead of misreading requests
+pub const PROTOCOL_VERSION: u32 = 2;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Request {
+    pub version: u32,
+    #[serde(default)]
+    pub image: Option<String>,
+    #[serde(default)]
+    pub affect_all: bool,
+    // display name to image or directory, displays without an entry fall back to `image`
+    #[serde(default)]
+    pub per_display: Option<HashMap<String, String>>,
+    // every display gets its own image instead of a slice of one spanning image
+    #[serde(default)]
+    pub independent: bool,
+    // display profile selected by `SetProfile`, none returns to matching the connected displays
+    #[serde(default)]
+    pub profile: Option<String>,
+    // started by `StartRotation`, the overrides of the request apply to all of its wallpapers
+    #[serde(default)]
+    pub rotation: Option<Rotation>,
+    // `ClearCache` removes the files cached for this layout, alone or along with `image`
+    #[serde(default)]
+    pub cache_layout: Option<String>,
+    #[serde(default)]
+    pub overrides: Overrides,
+    pub commands: Vec<Command>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Command {
+    SetWallpaper,
+    CacheWallpaper,
+    SetColors,
+    CacheColors,
+    GetWallpaperCache,
+    GetColorCache,
+    GetCurrentScheme,
+    GetConfig,
+    ValidateConfig,
+    GetState,
+    GetDisplays,
+    SetProfile,
+    GetJob { id: u64 },
+    ListJobs,
+    CancelJob { id: u64 },
+    // rotations are addressed by id, none addresses all of them
+    StartRotation,
+    PauseRotation { id: Option<u64> },
+    ResumeRotation { id: Option<u64> },
+    StopRotation { id: Option<u64> },
+    RotateNext { id: Option<u64> },
+    RotatePrev { id: Option<u64> },
+    // step through the wallpapers set before
+    PrevWallpaper,
+    NextWallpaper,
+    Undo,
+    GetHistory,
+    GetCacheStats,
+    PruneCache,
+    ClearCache,
+    Subscribe,
+    Shutdown,
+}
+
+impl Command {
+    #[must_use]
+    pub fn requires_image(&self) -> bool {
+        matches!(
+            self,
+            Command::SetWallpaper
+                | Command::CacheWallpaper
+                | Command::SetColors
+                | Command::CacheColors
+                | Command::GetWallpaperCache
+                | Command::GetColorCache
+        )
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Overrides {
+    pub displays: Option<Vec<Display>>,
+    pub templates: Option<Vec<String>>,
+    pub resize_alg: Option<String>,
+    pub set_command: Option<String>,
+    pub fit: Option<String>,
+    pub background: Option<String>,
+    pub gravity: Option<String>,
+    pub focal_point: Option<String>,
+    pub contrast: Option<f32>,
+    pub brightness: Option<i32>,
+    pub hue: Option<i32>,
+    pub blur: Option<f32>,
+    pub invert: Option<bool>,
+    pub flip_h: Option<bool>,
+    pub flip_v: Option<bool>,
+    pub rwal_thumb: Option<String>,
+    pub rwal_clamp: Option<String>,
+    pub rwal_accent: Option<u32>,
+    pub rwal_order: Option<String>,
+}
+
+impl Request {
+    #[must_use]
+    pub fn new(commands: Vec<Command>) -> Self {
+        Request {
+            version: PROTOCOL_VERSION,
+            image: None,
+            affect_all: false,
+            per_display: None,
+            independent: false,
+            profile: None,
+            rotation: None,
+            cache_layout: None,
+            overrides: Overrides::default(),
+            commands,
+        }
+    }
+
+    #[must_use]
+    pub fn from_args(input: &[String]) -> Self {
+        let has = |arg: &str| input.iter().any(|el| el == arg);
+
+        let mut commands = Vec::new();
+
+        if has("--get-config") {
+            commands.push(Command::GetConfig);
+        }
+        if has("--validate-config") {
+            commands.push(Command::ValidateConfig);
+        }
+        if has("--get-state") {
+            commands.push(Command::GetState);
+        }
+        if has("--get-displays") {
+            commands.push(Command::GetDisplays);
+        }
+        let profile = get_value::<String>(input, "--profile");
+        if profile.is_some() {
+            commands.push(Command::SetProfile);
+        }
+        for (flag, command) in [
+            ("--get-history", Command::GetHistory),
+            ("--prev", Command::PrevWallpaper),
+            ("--next", Command::NextWallpaper),
+            ("--undo", Command::Undo),
+            ("--cache-stats", Command::GetCacheStats),
+            ("--cache-prune", Command::PruneCache),
+            ("--cache-clear", Command::ClearCache),
+            ("--get-current-scheme", Command::GetCurrentScheme),
+            ("--get-w-cache", Command::GetWallpaperCache),
+            ("--get-c-cache", Command::GetColorCache),
+            ("--subscribe", Command::Subscribe),
+            ("--shutdown", Command::Shutdown),
+            ("--jobs", Command::ListJobs),
+        ] {
+            if has(flag) {
+                commands.push(command);
+            }
+        }
+        if let Some(id) = get_value::<u64>(input, "--job") {
+            commands.push(Command::GetJob { id });
+        }
+        if let Some(id) = get_value::<u64>(input, "--cancel") {
+            commands.push(Command::CancelJob { id });
+        }
+        let rotation = get_rotation_value(input);
+        if rotation.is_some() {
+            commands.push(Command::StartRotation);
+        }
+        push_rotation_commands(input, &mut commands);
+        // -S and -T describe the rotation, its wallpapers are set by the daemon over time
+        if rotation.is_none() {
+            if has("-T") {
+                commands.push(Command::SetColors);
+            } else if has("-C") {
+                commands.push(Command::CacheColors);
+            }
+            if has("-S") {
+                commands.push(Command::SetWallpaper);
+            } else if has("-W") {
+                commands.push(Command::CacheWallpaper);
+            }
+        }
+
+        let overrides = Overrides {
+            displays: get_displays_value(input, "--displays"),
+            templates: get_templates_value(input, "--templates"),
+            resize_alg: get_value::<String>(input, "--resize-alg"),
+            set_command: get_value::<String>(input, "--set-command"),
+            fit: get_value::<String>(input, "--fit"),
+            background: get_value::<String>(input, "--background"),
+            gravity: get_value::<String>(input, "--gravity"),
+            focal_point: get_value::<String>(input, "--focal"),
+            contrast: get_value::<f32>(input, "--contrast"),
+            brightness: get_value::<i32>(input, "--brightness"),
+            hue: get_value::<i32>(input, "--hue"),
+            blur: get_value::<f32>(input, "--blur"),
+            invert: has("--invert").then_some(true),
+            flip_h: has("--fliph").then_some(true),
+            flip_v: has("--flipv").then_some(true),
+            rwal_thumb: get_value::<String>(input, "--thumb"),
+            rwal_clamp: get_value::<String>(input, "--clamp"),
+            rwal_accent: get_value::<u32>(input, "--accent"),
+            rwal_order: get_value::<String>(input, "--order"),
+        };
+
+        Request {
+            version: PROTOCOL_VERSION,
+            image: get_value::<String>(input, "-I"),
+            affect_all: has("-A"),
+            per_display: get_per_display_value(input, "--per-display"),
+            independent: has("--independent"),
+            profile: profile.filter(|profile| profile != "auto"),
+            rotation,
+            cache_layout: get_value::<String>(input, "--cache-layout"),
+            overrides,
+            commands,
+        }
+    }
+}
+
+pub(crate) fn get_value<T: std::str::FromStr>(list: &[String], prev_element: &str) -> Option<T> {
+    for (i, el) in list.iter().enumerate() {
+        if el == prev_element && i + 1 < list.len() {
+            return list[i + 1].parse::<T>().ok();
+        }
+    }
+    None
+}
+
+fn get_rotation_value(list: &[String]) -> Option<Rotation> {
+    let source = get_value::<String>(list, "--rotate")?;
+    Some(Rotation {
+        source: source.parse().ok()?,
+        // an invalid interval is sent as 0 and refused by the daemon
+        interval: get_value::<String>(list, "--interval")
+            .map_or(Some(DEFAULT_ROTATION_INTERVAL), |i| parse_interval(&i))
+            .unwrap_or(0),
+        order: get_value::<String>(list, "--rotate-order")
+            .and_then(|order| order.parse().ok())
+            .unwrap_or_default(),
+        colors: list.iter().any(|el| el == "-T"),
+    })
+}
+
+// the id of a rotation is optional, without it every rotation is addressed
+fn push_rotation_commands(list: &[String], commands: &mut Vec<Command>) {
+    let id = |flag: &str| {
+        list.iter()
+            .any(|el| el == flag)
+            .then(|| get_value::<u64>(list, flag))
+    };
+    if let Some(id) = id("--rotate-pause") {
+        commands.push(Command::PauseRotation { id });
+    }
+    if let Some(id) = id("--rotate-resume") {
+        commands.push(Command::ResumeRotation { id });
+    }
+    if let Some(id) = id("--rotate-stop") {
+        commands.push(Command::StopRotation { id });
+    }
+    if let Some(id) = id("--rotate-next") {
+        commands.push(Command::RotateNext { id });
+    }
+    if let Some(id) = id("--rotate-prev") {
+        commands.push(Command::RotatePrev { id });
+    }
+}
+
+fn get_displays_value(list: &[String], prev_element: &str) -> Option<Vec<Display>> {
+    let mut displays: Vec<Display> = Vec::new();
+
+    if let Some(raw_displays) = get_value::<String>(list, prev_element) {
+        for raw_display in raw_displays.split(';') {
+            if let Ok(display) = raw_display.parse::<Display>() {
+                displays.push(display);
+            }
+        }
+    }
+
+    if !displays.is_empty() {
+        return Some(displays);
+    }
+    None
+}
+
+fn get_per_display_value(list: &[String], prev_element: &str) -> Option<HashMap<String, String>> {
+    let mut images: HashMap<String, String> = HashMap::new();
+
+    if let Some(raw_images) = get_value::<String>(list, prev_element) {
+        for raw_image in raw_images.split(';') {
+            if let Some((display, image)) = raw_image.split_once('=') {
+                images.insert(display.to_string(), image.to_string());
+            }
+        }
+    }
+
+    if !images.is_empty() {
+        return Some(images);
+    }
+    None
+}
+
+fn get_templates_value(list: &[String], prev_element: &str) -> Option<Vec<String>> {
+    let mut templates: Vec<String> = Vec::new();
+
+    if let Some(raw_templates) = get_value::<String>(list, prev_element) {
+        templates = raw_templates.split(';').map(ToString::to_string).collect();
+    }
+
+    if !templates.is_empty() {
+        return Some(templates);
+    }
+    None
+}
@@ -0,0 +1,89 @@
+use serde_derive::{Deserialize, Serialize};
+use std::str::FromStr;
+
+pub const DEFAULT_ROTATION_INTERVAL: u64 = 30 * 60;
+
+// wallpapers taken from `source` one after another, every `interval` seconds
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Rotation {
+    pub source: RotationSource,
+    pub interval: u64,
+    #[serde(default)]
+    pub order: RotationOrder,
+    // the colorscheme is applied along with every wallpaper
+    #[serde(default)]
+    pub colors: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RotationSource {
+    Directory(String),
+    List(Vec<String>),
+    // a file with one image path per line, `#` starts a comment like in m3u playlists
+    Playlist(String),
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RotationOrder {
+    // any image but the current one
+    #[default]
+    Random,
+    // every image once before any is repeated
+    Shuffle,
+    Name,
+    Mtime,
+}
+
+impl FromStr for RotationSource {
+    type Err = String;
+
+    // `a;b;c` is a list, `.m3u`, `.m3u8` and `.txt` files are playlists, anything else a directory
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains(';') {
+            return Ok(RotationSource::List(
+                s.split(';')
+                    .filter(|path| !path.is_empty())
+                    .map(ToString::to_string)
+                    .collect(),
+            ));
+        }
+        let extension = s.rsplit_once('.').map(|(_, e)| e.to_lowercase());
+        match extension.as_deref() {
+            Some("m3u" | "m3u8" | "txt") => Ok(RotationSource::Playlist(s.to_string())),
+            _ => Ok(RotationSource::Directory(s.to_string())),
+        }
+    }
+}
+
+impl FromStr for RotationOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(RotationOrder::Random),
+            "shuffle" => Ok(RotationOrder::Shuffle),
+            "name" => Ok(RotationOrder::Name),
+            "mtime" => Ok(RotationOrder::Mtime),
+            _ => Err(format!("unknown rotation order {s}")),
+        }
+    }
+}
+
+// `90`, `90s`, `15m`, `2h` or `1d`, in seconds
+#[must_use]
+pub fn parse_interval(s: &str) -> Option<u64> {
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => s.split_at(index),
+        None => (s, "s"),
+    };
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
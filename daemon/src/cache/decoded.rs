@@ -0,0 +1,74 @@
+use std::sync::{Arc, Mutex};
+
+use image::DynamicImage;
+
+use crate::cache::source::source_key;
+use crate::daemon::pool::pool;
+use crate::wallpaper::image::open_image;
+
+// key of an image to the image, the most recently used last
+static DECODED: Mutex<Vec<(String, Arc<DynamicImage>)>> = Mutex::new(Vec::new());
+
+// the image as it was decoded, shared by every request for the same content
+pub fn original(image_path: &str) -> Result<Arc<DynamicImage>, String> {
+    let source = source_key(image_path)?;
+    get_or_insert(format!("{source}:original"), || open_image(image_path))
+}
+
+// an image derived from the original, like the original scaled for a layout. `variant`
+// names everything `derive` depends on besides the content of the image
+pub fn derived(
+    image_path: &str,
+    variant: &str,
+    derive: impl FnOnce(&DynamicImage) -> DynamicImage,
+) -> Result<Arc<DynamicImage>, String> {
+    let source = source_key(image_path)?;
+    get_or_insert(format!("{source}:{variant}"), || {
+        original(image_path).map(|image| derive(&image))
+    })
+}
+
+// decoded and scaled outside of the lock, the same image may rarely be made twice
+fn get_or_insert(
+    key: String,
+    make: impl FnOnce() -> Result<DynamicImage, String>,
+) -> Result<Arc<DynamicImage>, String> {
+    if let Some(image) = lookup(&key) {
+        return Ok(image);
+    }
+    let image = Arc::new(make()?);
+    insert(key, image.clone());
+    Ok(image)
+}
+
+fn lookup(key: &str) -> Option<Arc<DynamicImage>> {
+    let mut decoded = DECODED.lock().unwrap();
+    let index = decoded.iter().position(|(k, _)| k == key)?;
+    let entry = decoded.remove(index);
+    let image = entry.1.clone();
+    decoded.push(entry);
+    Some(image)
+}
+
+fn insert(key: String, image: Arc<DynamicImage>) {
+    let budget = budget();
+    if size(&image) > budget {
+        return;
+    }
+    let mut decoded = DECODED.lock().unwrap();
+    decoded.retain(|(k, _)| *k != key);
+    decoded.push((key, image));
+    while decoded.iter().map(|(_, image)| size(image)).sum::<usize>() > budget {
+        decoded.remove(0);
+    }
+}
+
+// decoded images kept in memory take a quarter of the memory of the workers, the least
+// recently used go first once they take more
+fn budget() -> usize {
+    usize::try_from(pool().memory_budget() / 4).unwrap_or(usize::MAX)
+}
+
+fn size(image: &DynamicImage) -> usize {
+    image.as_bytes().len()
+}
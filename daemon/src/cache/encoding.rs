@@ -0,0 +1,125 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::codecs::webp::WebPEncoder;
+use image::{DynamicImage, ImageFormat};
+use serde::{Deserialize, Serialize};
+
+use crate::cache::store::write_atomic;
+
+pub const DEFAULT_QUALITY: u8 = 90;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheFormat {
+    // the format of the source image
+    #[default]
+    Original,
+    Png,
+    #[serde(alias = "jpg")]
+    Jpeg,
+    // always lossless
+    Webp,
+}
+
+// png compression level, `fast` is what the png encoder picks on its own
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    Fast,
+    Balanced,
+    Best,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Encoding {
+    pub format: CacheFormat,
+    // jpeg quality, 1 to 100
+    pub quality: u8,
+    pub compression: Compression,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding {
+            format: CacheFormat::default(),
+            quality: DEFAULT_QUALITY,
+            compression: Compression::default(),
+        }
+    }
+}
+
+impl Compression {
+    fn name(self) -> &'static str {
+        match self {
+            Compression::Fast => "fast",
+            Compression::Balanced => "balanced",
+            Compression::Best => "best",
+        }
+    }
+
+    fn png(self) -> CompressionType {
+        match self {
+            Compression::Fast => CompressionType::Fast,
+            Compression::Balanced => CompressionType::Default,
+            Compression::Best => CompressionType::Best,
+        }
+    }
+}
+
+impl Encoding {
+    // part of the cache key, so changing the encoding caches the wallpapers again
+    pub fn key(self) -> String {
+        match self.format {
+            CacheFormat::Original => {
+                format!("original:{}:{}", self.quality, self.compression.name())
+            }
+            CacheFormat::Png => format!("png:{}", self.compression.name()),
+            CacheFormat::Jpeg => format!("jpeg:{}", self.quality),
+            CacheFormat::Webp => "webp".to_string(),
+        }
+    }
+
+    pub fn extension(self, source_extension: &str) -> String {
+        match self.format {
+            CacheFormat::Original => source_extension.to_string(),
+            CacheFormat::Png => "png".to_string(),
+            CacheFormat::Jpeg => "jpg".to_string(),
+            CacheFormat::Webp => "webp".to_string(),
+        }
+    }
+
+    // a backend never reads a half written file
+    pub fn save(self, image: &DynamicImage, path: &str) -> Result<(), String> {
+        write_atomic(path, |temp_path| self.write(image, path, temp_path))
+            .map_err(|e| format!("unable to save {path}: {e}"))
+    }
+
+    fn write(self, image: &DynamicImage, path: &str, temp_path: &str) -> Result<(), String> {
+        let extension = path.rsplit('.').next().unwrap_or_default().to_lowercase();
+        let mut writer = BufWriter::new(File::create(temp_path).map_err(|e| e.to_string())?);
+
+        match extension.as_str() {
+            "png" => image.write_with_encoder(PngEncoder::new_with_quality(
+                &mut writer,
+                self.compression.png(),
+                FilterType::Adaptive,
+            )),
+            // jpeg has no alpha channel
+            "jpg" | "jpeg" => DynamicImage::ImageRgb8(image.to_rgb8())
+                .write_with_encoder(JpegEncoder::new_with_quality(&mut writer, self.quality)),
+            "webp" => DynamicImage::ImageRgba8(image.to_rgba8())
+                .write_with_encoder(WebPEncoder::new_lossless(&mut writer)),
+            _ => match ImageFormat::from_extension(&extension) {
+                Some(format) => image.write_to(&mut writer, format),
+                None => return Err(format!("unknown image format `{extension}`")),
+            },
+        }
+        .map_err(|e| e.to_string())?;
+
+        writer.flush().map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,493 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cache::source::{live_sources, source_key};
+use crate::cache::store::{write_atomic, DelayedSave};
+use crate::daemon::config::Config;
+use crate::daemon::state::current_state;
+use crate::logger::logger::{info, warn};
+use crate::{paths, unix_timestamp};
+use common::Display;
+
+// files written this recently may belong to a request which did not record them yet
+const UNTRACKED_GRACE: Duration = Duration::from_secs(60);
+
+static MANIFEST: Mutex<Option<Manifest>> = Mutex::new(None);
+static SAVE: DelayedSave = DelayedSave::new("manifest", save);
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheKind {
+    Wallpaper,
+    Palette,
+}
+
+// content hash of an image to the files cached from it
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Manifest {
+    sources: BTreeMap<String, CachedSource>,
+    // files between being cached and being set, with the number of requests setting them
+    #[serde(skip)]
+    pinned: HashMap<String, usize>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CachedSource {
+    // the path the image was last used from
+    pub image: String,
+    pub files: BTreeMap<String, CachedFile>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CachedFile {
+    pub kind: CacheKind,
+    // names of the displays a wallpaper was cropped for, joined with `+`
+    pub layout: Option<String>,
+    pub profile: Option<String>,
+    pub size: u64,
+    pub created_at: u128,
+    pub used_at: u128,
+}
+
+#[derive(Default, Serialize)]
+pub struct CacheStats {
+    pub files: usize,
+    pub size: u64,
+    pub wallpapers: usize,
+    pub wallpapers_size: u64,
+    pub palettes: usize,
+    pub palettes_size: u64,
+    // files in the cache directories the manifest does not know about
+    pub untracked: usize,
+    pub untracked_size: u64,
+    pub max_size: Option<u64>,
+    pub max_age: Option<u64>,
+    pub sources: BTreeMap<String, CachedSource>,
+}
+
+// keeps files from being evicted until it is dropped
+pub struct Pinned {
+    files: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct Removed {
+    pub files: usize,
+    pub size: u64,
+}
+
+impl fmt::Display for Removed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} files ({})", self.files, format_size(self.size))
+    }
+}
+
+// files about to be set are pinned before they are cached, so the eviction of another
+// request does not remove them before they are on screen
+pub fn pin(files: &[String]) -> Pinned {
+    with_manifest(|manifest| {
+        for path in files {
+            *manifest.pinned.entry(path.clone()).or_default() += 1;
+        }
+    });
+    Pinned {
+        files: files.to_vec(),
+    }
+}
+
+// cached files are recorded when they are created and every time they are used, the budget
+// of the config is enforced afterwards without touching the recorded files
+pub fn record(
+    config: &Config,
+    image_path: &str,
+    kind: CacheKind,
+    layout: Option<&[Display]>,
+    files: &[String],
+) {
+    let Ok(source) = source_key(image_path) else {
+        return;
+    };
+    let image = fs::canonicalize(image_path).map_or_else(
+        |_| image_path.to_string(),
+        |path| path.to_string_lossy().to_string(),
+    );
+    let protected = protected(files);
+    let now = unix_timestamp();
+
+    update(|manifest| {
+        let cached = manifest.sources.entry(source).or_default();
+        cached.image = image;
+        for path in files {
+            let Ok(metadata) = fs::metadata(path) else {
+                continue;
+            };
+            let file = cached
+                .files
+                .entry(path.clone())
+                .or_insert_with(|| CachedFile {
+                    kind,
+                    layout: layout.map(layout_name),
+                    profile: layout.and(config.active_profile.clone()),
+                    size: 0,
+                    created_at: now,
+                    used_at: now,
+                });
+            file.size = metadata.len();
+            file.used_at = now;
+        }
+        evict(manifest, config, &protected);
+    });
+}
+
+// applies the budget of a new config
+pub fn enforce(config: &Config) {
+    let protected = protected(&[]);
+    update(|manifest| evict(manifest, config, &protected));
+}
+
+// removes the files of images which were deleted or changed, and the files the manifest
+// does not know about, like the ones cached before it existed
+pub fn prune() -> Removed {
+    let live = live_sources();
+    let protected = protected(&[]);
+
+    update(|manifest| {
+        let mut removed = Removed::default();
+        for (source, path, size) in files(manifest) {
+            if !Path::new(&path).exists() {
+                forget(manifest, &source, &path);
+            } else if !live.contains(&source)
+                && !protected.contains(&path)
+                && !manifest.pinned.contains_key(&path)
+            {
+                remove(manifest, &source, &path, size, &mut removed);
+            }
+        }
+        for (path, size) in untracked(manifest) {
+            if delete(&path) {
+                removed.files += 1;
+                removed.size += size;
+            }
+        }
+        if removed.files > 0 {
+            info(&format!("Pruned {removed} of orphaned cache."));
+        }
+        removed
+    })
+}
+
+// removes the files cached from any of the images, or for a layout, which is a profile,
+// the displays of a layout joined with `+` or one display of it
+pub fn clear(images: &[String], layout: Option<&str>) -> Removed {
+    let sources: HashSet<String> = images
+        .iter()
+        .filter_map(|image| source_key(image).ok())
+        .collect();
+    // deleted images are matched by the path they were used from
+    let paths: HashSet<String> = images
+        .iter()
+        .map(|image| {
+            fs::canonicalize(image)
+                .map_or_else(|_| image.clone(), |path| path.to_string_lossy().to_string())
+        })
+        .collect();
+
+    update(|manifest| {
+        let mut removed = Removed::default();
+        let matching: Vec<(String, String, u64)> = manifest
+            .sources
+            .iter()
+            .filter(|(source, cached)| {
+                images.is_empty() || sources.contains(*source) || paths.contains(&cached.image)
+            })
+            .flat_map(|(source, cached)| {
+                cached
+                    .files
+                    .iter()
+                    .filter(|(_, file)| layout.map_or(true, |layout| file.in_layout(layout)))
+                    .map(move |(path, file)| (source.clone(), path.clone(), file.size))
+            })
+            .collect();
+        for (source, path, size) in matching {
+            remove(manifest, &source, &path, size, &mut removed);
+        }
+        if removed.files > 0 {
+            info(&format!("Cleared {removed} of cache."));
+        }
+        removed
+    })
+}
+
+pub fn stats(config: &Config) -> CacheStats {
+    with_manifest(|manifest| {
+        let mut stats = CacheStats {
+            max_size: config.cache_max_size,
+            max_age: config.cache_max_age,
+            sources: manifest.sources.clone(),
+            ..CacheStats::default()
+        };
+        for file in manifest
+            .sources
+            .values()
+            .flat_map(|cached| cached.files.values())
+        {
+            stats.files += 1;
+            stats.size += file.size;
+            match file.kind {
+                CacheKind::Wallpaper => {
+                    stats.wallpapers += 1;
+                    stats.wallpapers_size += file.size;
+                }
+                CacheKind::Palette => {
+                    stats.palettes += 1;
+                    stats.palettes_size += file.size;
+                }
+            }
+        }
+        for (_, size) in untracked(manifest) {
+            stats.untracked += 1;
+            stats.untracked_size += size;
+        }
+        stats
+    })
+}
+
+// sizes like `500M` or `2G`, a bare number is in bytes
+pub fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => s.split_at(index),
+        None => (s, ""),
+    };
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{bytes} B"),
+        1024..1_048_576 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        1_048_576..1_073_741_824 => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+        _ => format!("{:.1} GB", bytes as f64 / 1_073_741_824.0),
+    }
+}
+
+impl CachedFile {
+    fn in_layout(&self, layout: &str) -> bool {
+        self.profile.as_deref() == Some(layout)
+            || self
+                .layout
+                .as_deref()
+                .is_some_and(|names| names == layout || names.split('+').any(|n| n == layout))
+    }
+}
+
+fn layout_name(displays: &[Display]) -> String {
+    displays
+        .iter()
+        .map(|display| display.name.as_str())
+        .collect::<Vec<&str>>()
+        .join("+")
+}
+
+// the wallpapers on screen are kept along with the given files
+fn protected(files: &[String]) -> HashSet<String> {
+    current_state()
+        .displays
+        .into_iter()
+        .map(|display| display.path)
+        .chain(files.iter().cloned())
+        .collect()
+}
+
+// least recently used files go first, until none is older than the maximum age and
+// the cache fits in the maximum size
+fn evict(manifest: &mut Manifest, config: &Config, protected: &HashSet<String>) {
+    if config.cache_max_size.is_none() && config.cache_max_age.is_none() {
+        return;
+    }
+    let max_age = config.cache_max_age.map(|age| u128::from(age) * 1000);
+    let now = unix_timestamp();
+
+    let mut files: Vec<(u128, String, String, u64)> = manifest
+        .sources
+        .iter()
+        .flat_map(|(source, cached)| {
+            cached
+                .files
+                .iter()
+                .map(move |(path, file)| (file.used_at, source.clone(), path.clone(), file.size))
+        })
+        .collect();
+    files.sort();
+    let mut total: u64 = files.iter().map(|(_, _, _, size)| size).sum();
+
+    let mut removed = Removed::default();
+    for (used_at, source, path, size) in files {
+        let expired = max_age.is_some_and(|age| now.saturating_sub(used_at) > age);
+        let over = config.cache_max_size.is_some_and(|max| total > max);
+        if !expired && !over {
+            break;
+        }
+        if protected.contains(&path) || manifest.pinned.contains_key(&path) {
+            continue;
+        }
+        remove(manifest, &source, &path, size, &mut removed);
+        total -= size;
+    }
+    if removed.files > 0 {
+        info(&format!("Evicted {removed} of cache over the budget."));
+    }
+}
+
+fn files(manifest: &Manifest) -> Vec<(String, String, u64)> {
+    manifest
+        .sources
+        .iter()
+        .flat_map(|(source, cached)| {
+            cached
+                .files
+                .iter()
+                .map(move |(path, file)| (source.clone(), path.clone(), file.size))
+        })
+        .collect()
+}
+
+fn remove(manifest: &mut Manifest, source: &str, path: &str, size: u64, removed: &mut Removed) {
+    if delete(path) {
+        removed.files += 1;
+        removed.size += size;
+    }
+    forget(manifest, source, path);
+}
+
+fn forget(manifest: &mut Manifest, source: &str, path: &str) {
+    if let Some(cached) = manifest.sources.get_mut(source) {
+        cached.files.remove(path);
+        if cached.files.is_empty() {
+            manifest.sources.remove(source);
+        }
+    }
+}
+
+fn delete(path: &str) -> bool {
+    match fs::remove_file(path) {
+        Ok(()) => true,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+        Err(e) => {
+            warn(&format!("Unable to remove {path}: {e}"));
+            false
+        }
+    }
+}
+
+// only files named by a cache key are considered, the current colorscheme lives next to them
+fn untracked(manifest: &Manifest) -> Vec<(String, u64)> {
+    let known: HashSet<&String> = manifest
+        .sources
+        .values()
+        .flat_map(|cached| cached.files.keys())
+        .collect();
+    let recent = SystemTime::now()
+        .checked_sub(UNTRACKED_GRACE)
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+
+    let mut files = Vec::new();
+    for dir in [paths().wallpapers_dir(), paths().colors_dir()] {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let key = name.split('.').next().unwrap_or_default();
+            if key.len() != 64 || !key.chars().all(|c| c.is_ascii_hexdigit()) {
+                continue;
+            }
+            let path = format!("{dir}/{name}");
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if !metadata.is_file()
+                || known.contains(&path)
+                || metadata.modified().is_ok_and(|time| time > recent)
+            {
+                continue;
+            }
+            files.push((path, metadata.len()));
+        }
+    }
+    files
+}
+
+// written with pending changes on shutdown
+pub fn flush() {
+    SAVE.flush();
+}
+
+impl Drop for Pinned {
+    fn drop(&mut self) {
+        with_manifest(|manifest| {
+            for path in &self.files {
+                if let Some(count) = manifest.pinned.get_mut(path) {
+                    *count -= 1;
+                    if *count == 0 {
+                        manifest.pinned.remove(path);
+                    }
+                }
+            }
+        });
+    }
+}
+
+fn update<T>(f: impl FnOnce(&mut Manifest) -> T) -> T {
+    let result = with_manifest(f);
+    SAVE.schedule();
+    result
+}
+
+fn with_manifest<T>(f: impl FnOnce(&mut Manifest) -> T) -> T {
+    let mut guard = MANIFEST.lock().unwrap();
+    f(guard.get_or_insert_with(load))
+}
+
+fn save() {
+    let data = {
+        let guard = MANIFEST.lock().unwrap();
+        let Some(manifest) = guard.as_ref() else {
+            return;
+        };
+        serde_json::to_string(manifest)
+    };
+    let path = paths().manifest_path();
+    let result = data.map_err(|e| e.to_string()).and_then(|data| {
+        write_atomic(&path, |temp_path| {
+            fs::write(temp_path, data).map_err(|e| e.to_string())
+        })
+    });
+    if let Err(e) = result {
+        warn(&format!("Unable to save cache manifest: {e}"));
+    }
+}
+
+fn load() -> Manifest {
+    fs::read_to_string(paths().manifest_path())
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
@@ -0,0 +1,5 @@
+pub mod decoded;
+pub mod encoding;
+pub mod manifest;
+pub mod source;
+pub mod store;
@@ -0,0 +1,128 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::cache::store::{write_atomic, DelayedSave};
+use crate::logger::logger::{info, warn};
+use crate::paths;
+
+// canonical path of an image to what it was when its content was hashed
+static INDEX: Mutex<Option<HashMap<String, Source>>> = Mutex::new(None);
+static SAVE: DelayedSave = DelayedSave::new("image index", save);
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Source {
+    size: u64,
+    // nanoseconds since the epoch
+    modified: u128,
+    hash: String,
+}
+
+// cached wallpapers and palettes are keyed by the content of the image, so moved or renamed
+// images keep their cache and images edited in place get a new one. the content is hashed
+// again only when the size or modification time differ from the ones in the index
+pub fn source_key(image_path: &str) -> Result<String, String> {
+    let error = |e: io::Error| format!("unable to read {image_path}: {e}");
+    let path = fs::canonicalize(image_path).map_err(error)?;
+    let (size, modified) = file_state(&path).map_err(error)?;
+    let path = path.to_string_lossy().to_string();
+
+    let known = with_index(|index| index.get(&path).cloned());
+    if let Some(known) = &known {
+        if known.size == size && known.modified == modified {
+            return Ok(known.hash.clone());
+        }
+    }
+
+    // hashed outside of the lock, jobs read many images at once
+    let hash = hash_file(&path).map_err(error)?;
+    if known.is_some_and(|known| known.hash != hash) {
+        info(&format!("{image_path} changed, its cache is created again."));
+    }
+    with_index(|index| {
+        index.insert(
+            path,
+            Source {
+                size,
+                modified,
+                hash: hash.clone(),
+            },
+        );
+    });
+    SAVE.schedule();
+    Ok(hash)
+}
+
+// hashes of the images which are unchanged since they were hashed, nothing is read again.
+// removed and changed images are dropped from the index, a changed one is hashed again
+// once it is used
+pub fn live_sources() -> HashSet<String> {
+    let live = with_index(|index| {
+        index.retain(|path, source| {
+            file_state(Path::new(path))
+                .is_ok_and(|state| state == (source.size, source.modified))
+        });
+        index.values().map(|source| source.hash.clone()).collect()
+    });
+    SAVE.schedule();
+    live
+}
+
+// written with pending changes on shutdown
+pub fn flush() {
+    SAVE.flush();
+}
+
+// size and modification time in nanoseconds since the epoch
+fn file_state(path: &Path) -> io::Result<(u64, u128)> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_nanos());
+    Ok((metadata.len(), modified))
+}
+
+fn hash_file(path: &str) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn with_index<T>(f: impl FnOnce(&mut HashMap<String, Source>) -> T) -> T {
+    let mut guard = INDEX.lock().unwrap();
+    f(guard.get_or_insert_with(load))
+}
+
+fn save() {
+    let data = {
+        let guard = INDEX.lock().unwrap();
+        let Some(index) = guard.as_ref() else {
+            return;
+        };
+        serde_json::to_string(index)
+    };
+    let path = paths().sources_path();
+    let result = data.map_err(|e| e.to_string()).and_then(|data| {
+        write_atomic(&path, |temp_path| {
+            fs::write(temp_path, data).map_err(|e| e.to_string())
+        })
+    });
+    if let Err(e) = result {
+        warn(&format!("Unable to save image index: {e}"));
+    }
+}
+
+fn load() -> HashMap<String, Source> {
+    fs::read_to_string(paths().sources_path())
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
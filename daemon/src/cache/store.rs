@@ -0,0 +1,88 @@
+use std::fs;
+use std::mem;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex, Once};
+use std::thread;
+use std::time::Duration;
+
+use crate::logger::logger::err;
+
+// files changed in bursts, like by jobs, are written once the burst is over
+const SAVE_DELAY: Duration = Duration::from_secs(1);
+
+// keeps temporary files written at the same time apart
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// `write` fills a temporary file next to `path` which is renamed over it, so a reader never
+// sees a half written file and a crash leaves the previous one
+pub fn write_atomic(
+    path: &str,
+    write: impl FnOnce(&str) -> Result<(), String>,
+) -> Result<(), String> {
+    let temp_path = format!(
+        "{path}.{}-{}.tmp",
+        process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    let result =
+        write(&temp_path).and_then(|()| fs::rename(&temp_path, path).map_err(|e| e.to_string()));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+// saves a file of the cache from a thread of its own a while after it changed, changes made
+// meanwhile are written along
+pub struct DelayedSave {
+    name: &'static str,
+    save: fn(),
+    pending: Mutex<bool>,
+    wake: Condvar,
+    started: Once,
+}
+
+impl DelayedSave {
+    pub const fn new(name: &'static str, save: fn()) -> Self {
+        DelayedSave {
+            name,
+            save,
+            pending: Mutex::new(false),
+            wake: Condvar::new(),
+            started: Once::new(),
+        }
+    }
+
+    pub fn schedule(&'static self) {
+        self.started.call_once(|| {
+            let spawned = thread::Builder::new()
+                .name(format!("{} save thread", self.name))
+                .spawn(move || self.run());
+            if let Err(e) = spawned {
+                err(&format!("Unable to start {} save thread: {e}", self.name));
+            }
+        });
+        *self.pending.lock().unwrap() = true;
+        self.wake.notify_one();
+    }
+
+    // writes pending changes right away, used on shutdown
+    pub fn flush(&self) {
+        if mem::take(&mut *self.pending.lock().unwrap()) {
+            (self.save)();
+        }
+    }
+
+    fn run(&self) {
+        loop {
+            let mut pending = self.pending.lock().unwrap();
+            while !*pending {
+                pending = self.wake.wait(pending).unwrap();
+            }
+            drop(pending);
+            thread::sleep(SAVE_DELAY);
+            self.flush();
+        }
+    }
+}
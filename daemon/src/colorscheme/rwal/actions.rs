@@ -1,10 +1,9 @@
 use std::fs;
 use std::path::Path;
 
-use crate::expand_user;
 use crate::wallpaper::image::ImageOperations;
 use crate::wallpaper::image::get_thumbed_image;
-use crate::COLORS_PATH;
+use crate::paths;
 
 use super::rwal::get_palette;
 use super::rwal_params::RwalParams;
@@ -14,19 +13,19 @@ pub fn run_rwal(
     color_scheme_path: &str,
     rwal_params: &RwalParams,
     image_ops: &ImageOperations,
-) -> Vec<String> {
+) -> Result<Vec<String>, String> {
     if !Path::new(color_scheme_path).exists() {
-        cache_rwal(image_path, color_scheme_path, rwal_params, image_ops);
+        cache_rwal(image_path, color_scheme_path, rwal_params, image_ops)?;
     }
 
     if let Ok(colors) = fs::read_to_string(color_scheme_path) {
-        let _ = fs::write(expand_user(COLORS_PATH), &colors);
+        let _ = fs::write(paths().colors_path(), &colors);
     }
 
-    if let Ok(colors) = fs::read_to_string(expand_user(COLORS_PATH)) {
-        return colors.lines().map(|line| line.to_string()).collect();
+    if let Ok(colors) = fs::read_to_string(paths().colors_path()) {
+        Ok(colors.lines().map(str::to_string).collect())
     } else {
-        Vec::new()
+        Ok(Vec::new())
     }
 }
 
@@ -35,13 +34,13 @@ pub fn cache_rwal(
     color_scheme_path: &str,
     rwal_params: &RwalParams,
     image_ops: &ImageOperations,
-) {
+) -> Result<(), String> {
     let image = &get_thumbed_image(
         image_path,
         image_ops,
         rwal_params.thumb_range.0,
         rwal_params.thumb_range.1,
-    );
+    )?;
 
     let pallete = get_palette(
         image,
@@ -51,6 +50,8 @@ pub fn cache_rwal(
     )
     .join("\n");
 
-    fs::write(color_scheme_path, &pallete).unwrap();
-    fs::write(expand_user(COLORS_PATH), &pallete).unwrap();
+    fs::write(color_scheme_path, &pallete)
+        .map_err(|e| format!("unable to write {color_scheme_path}: {e}"))?;
+    let colors_path = paths().colors_path();
+    fs::write(&colors_path, &pallete).map_err(|e| format!("unable to write {colors_path}: {e}"))
 }
@@ -1,76 +1,98 @@
-use std::{path::Path, thread};
+use std::path::Path;
 
+use crate::cache::manifest::{self, CacheKind};
+use crate::cache::source::source_key;
 use crate::colorscheme::rwal::rwal_params::OrderBy;
 use crate::daemon::config::Config;
+use crate::daemon::events::emit;
+use crate::daemon::pool::{current_priority, pool};
+use crate::daemon::state;
 use crate::encode_string;
 use crate::expand_user;
-use crate::get_image_name;
+use crate::paths;
 use crate::logger::logger::{err, log};
 use crate::template::template::Template;
 use crate::wallpaper::image::ImageOperations;
-use crate::COLORS_DIR;
+use common::Event;
 
 use super::rwal::{
     actions::{cache_rwal, run_rwal},
     rwal_params::RwalParams,
 };
 
-pub fn set_scheme(config: &Config, image_path: &str) {
+pub fn set_scheme(config: &Config, image_path: &str) -> Result<(), String> {
     if let Some(image_ops) = &config.image_operations {
         if let Some(rwal_params) = &config.rwal_params {
-            let cache_path = get_cache_path(image_ops, rwal_params, image_path);
-
-            if !Path::new(&cache_path).exists() {
-                cache_scheme(config, image_path);
+            let cache_path = get_cache_path(image_ops, rwal_params, image_path)?;
+
+            let _pinned = manifest::pin(std::slice::from_ref(&cache_path));
+            if Path::new(&cache_path).exists() {
+                let files = std::slice::from_ref(&cache_path);
+                manifest::record(config, image_path, CacheKind::Palette, None, files);
+            } else {
+                cache_scheme(config, image_path)?;
             }
 
-            let colors = run_rwal(image_path, &cache_path, rwal_params, image_ops);
+            let colors = run_rwal(image_path, &cache_path, rwal_params, image_ops)?;
+            emit(&Event::PaletteApplied {
+                image: image_path.to_string(),
+                colors: colors.clone(),
+            });
+            state::update(|state| {
+                state.palette.clone_from(&colors);
+                state.palette_image = Some(image_path.to_string());
+                state.image_operations = Some(image_ops.clone());
+                state.rwal_params = Some(rwal_params.clone());
+            });
 
             if let Some(templates) = &config.templates {
-                let mut handlers = Vec::new();
-
-                log("Applying templates...");
-
-                for template in templates {
-                    let template = template.clone();
-                    let colors = colors.clone();
-                    let thread = thread::spawn(move || {
-                        if let Ok(tem) = Template::new(&expand_user(&template)) {
-                            tem.apply(colors);
-                        }
-                    });
-                    handlers.push(thread);
-                }
-
-                for handle in handlers {
-                    handle.join().unwrap()
-                }
+                apply_templates(templates, &colors);
             }
         }
     }
+    Ok(())
+}
+
+pub fn apply_templates(templates: &[String], colors: &[String]) {
+    log("Applying templates...");
+
+    let tasks: Vec<Box<dyn FnOnce() + Send>> = templates
+        .iter()
+        .map(|template| {
+            let template = template.clone();
+            let colors = colors.to_vec();
+            Box::new(move || {
+                if let Ok(tem) = Template::new(&expand_user(&template)) {
+                    tem.apply(colors);
+                }
+            }) as Box<dyn FnOnce() + Send>
+        })
+        .collect();
+
+    if let Err(e) = pool().run(current_priority(), tasks) {
+        err(&format!("Failed to apply templates: {e}"));
+    }
 }
 
-pub fn cache_scheme(config: &Config, image_path: &str) {
+pub fn cache_scheme(config: &Config, image_path: &str) -> Result<(), String> {
     if config.image_operations.is_none() {
         err("Failed to cache colorscheme.");
         err("Image operations is None.");
-        return;
+        return Err("image operations are not configured".to_string());
     }
     if config.rwal_params.is_none() {
         err("Failed to cache colorscheme.");
         err("Rwal params is None.");
-        return;
+        return Err("rwal params are not configured".to_string());
     }
 
     let image_ops = config.image_operations.as_ref().unwrap();
     let rwal_params = config.rwal_params.as_ref().unwrap();
 
-    cache_rwal(
-        image_path,
-        &get_cache_path(image_ops, rwal_params, image_path),
-        rwal_params,
-        image_ops,
-    );
+    let cache_path = get_cache_path(image_ops, rwal_params, image_path)?;
+    cache_rwal(image_path, &cache_path, rwal_params, image_ops)?;
+    manifest::record(config, image_path, CacheKind::Palette, None, &[cache_path]);
+    Ok(())
 }
 
 pub fn get_cached_colors(config: &Config, image_path: &str) -> Option<Vec<String>> {
@@ -88,29 +110,34 @@ pub fn get_cached_colors(config: &Config, image_path: &str) -> Option<Vec<String
     let image_ops = config.image_operations.as_ref().unwrap();
     let rwal_params = config.rwal_params.as_ref().unwrap();
 
-    if !Path::new(&get_cache_path(image_ops, rwal_params, image_path)).exists() {
-        cache_scheme(config, image_path);
+    let cache_path = get_cache_path(image_ops, rwal_params, image_path).ok()?;
+    if Path::new(&cache_path).exists() {
+        let files = std::slice::from_ref(&cache_path);
+        manifest::record(config, image_path, CacheKind::Palette, None, files);
+    } else {
+        cache_scheme(config, image_path).ok()?;
     }
 
-    Some(run_rwal(
+    run_rwal(
         image_path,
-        &get_cache_path(image_ops, rwal_params, image_path),
+        &cache_path,
         rwal_params,
         image_ops,
-    ))
+    )
+    .ok()
 }
 
 fn get_cache_path(
     image_ops: &ImageOperations,
     rwal_params: &RwalParams,
     image_path: &str,
-) -> String {
-    format!(
+) -> Result<String, String> {
+    Ok(format!(
         "{}/{}",
-        expand_user(COLORS_DIR),
+        paths().colors_dir(),
         encode_string(&format!(
             "{}{}{}{}{}{}{}{}{}{}{}{}",
-            get_image_name(image_path),
+            source_key(image_path)?,
             image_ops.brightness,
             image_ops.contrast,
             image_ops.hue,
@@ -128,5 +155,5 @@ fn get_cache_path(
             rwal_params.thumb_range.1,
             rwal_params.colors,
         ))
-    )
+    ))
 }
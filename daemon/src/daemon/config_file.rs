@@ -0,0 +1,541 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cache::encoding::{CacheFormat, Compression, Encoding, DEFAULT_QUALITY};
+use crate::cache::manifest::parse_size;
+use crate::colorscheme::rwal::rwal_params::{OrderBy, RwalParams};
+use crate::daemon::config::{Config, DisplayOptions, Profile};
+use crate::expand_user;
+use crate::logger::logger::{err, warn};
+use crate::wallpaper::backend::BackendKind;
+use crate::wallpaper::crop::Gravity;
+use crate::wallpaper::fit::{Background, FitMode};
+use crate::wallpaper::image::ImageOperations;
+use crate::wallpaper::provider::ProviderKind;
+use common::paths::ConfigFormat;
+use common::rotation::parse_interval;
+use common::Display;
+
+const RESIZE_ALGORITHMS: [&str; 5] = ["Nearest", "Triangle", "CatmullRom", "Gaussian", "Lanczos3"];
+
+#[derive(Default, Serialize)]
+pub struct Diagnostics {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl Diagnostics {
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn log(&self, source: &str) {
+        for warning in &self.warnings {
+            warn(&format!("{source}: {warning}"));
+        }
+        for error in &self.errors {
+            err(&format!("{source}: {error}"));
+        }
+    }
+}
+
+// layout of the config file as written by the user, converted into `Config` once validated
+#[derive(Deserialize)]
+#[serde(default)]
+struct ConfigFile {
+    displays: Option<Vec<Display>>,
+    display_provider: Option<ProviderKind>,
+    display_command: Option<String>,
+    hotplug: bool,
+    hotplug_socket: Option<String>,
+    profiles: HashMap<String, Profile>,
+    profile: Option<String>,
+    templates: Vec<String>,
+    wall_command: Option<String>,
+    backend: Option<BackendKind>,
+    display_options: HashMap<String, DisplayOptions>,
+    fit: FitMode,
+    background: Background,
+    gravity: Gravity,
+    focal_point: Option<[f32; 2]>,
+    resize_algorithm: String,
+    last_call_file: Option<String>,
+    rwal: RwalSection,
+    impg: ImpgSection,
+    max_heavy_jobs: Option<usize>,
+    workers: Option<usize>,
+    worker_memory: Option<String>,
+    restore_state: bool,
+    rerender_on_template_change: bool,
+    cache_max_size: Option<String>,
+    cache_max_age: Option<String>,
+    cache_format: CacheFormat,
+    cache_quality: Option<u8>,
+    cache_compression: Option<Compression>,
+    socket_path: Option<String>,
+    cache_dir: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct RwalSection {
+    thumb_w: u32,
+    thumb_h: u32,
+    clamp_min: f32,
+    clamp_max: f32,
+    accent_color: u32,
+    rwal_colors: u32,
+    order_by: String,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct ImpgSection {
+    contrast: f32,
+    brightness: i32,
+    huerotate: i32,
+    blur: f32,
+    invert: bool,
+    flip_h: bool,
+    flip_v: bool,
+}
+
+impl Default for ConfigFile {
+    fn default() -> Self {
+        ConfigFile {
+            displays: None,
+            display_provider: None,
+            display_command: None,
+            hotplug: false,
+            hotplug_socket: None,
+            profiles: HashMap::new(),
+            profile: None,
+            templates: Vec::new(),
+            wall_command: None,
+            backend: None,
+            display_options: HashMap::new(),
+            fit: FitMode::default(),
+            background: Background::default(),
+            gravity: Gravity::default(),
+            focal_point: None,
+            resize_algorithm: "Triangle".to_string(),
+            last_call_file: None,
+            rwal: RwalSection::default(),
+            impg: ImpgSection::default(),
+            max_heavy_jobs: None,
+            workers: None,
+            worker_memory: None,
+            restore_state: false,
+            rerender_on_template_change: false,
+            cache_max_size: None,
+            cache_max_age: None,
+            cache_format: CacheFormat::default(),
+            cache_quality: None,
+            cache_compression: None,
+            socket_path: None,
+            cache_dir: None,
+        }
+    }
+}
+
+impl Default for RwalSection {
+    fn default() -> Self {
+        RwalSection {
+            thumb_w: 200,
+            thumb_h: 200,
+            clamp_min: 140.0,
+            clamp_max: 170.0,
+            accent_color: 4,
+            rwal_colors: 7,
+            order_by: "h".to_string(),
+        }
+    }
+}
+
+pub fn validate_file(path: &str) -> Diagnostics {
+    match fs::read_to_string(path) {
+        Ok(caption) => parse_config(&caption, ConfigFormat::from_path(path)).1,
+        Err(e) => Diagnostics {
+            errors: vec![format!("unable to read {path}: {e}")],
+            warnings: Vec::new(),
+        },
+    }
+}
+
+pub fn default_config() -> Config {
+    ConfigFile::default().into_config(&mut Diagnostics::default())
+}
+
+// the config is returned only when there are no errors
+pub fn parse_config(caption: &str, format: ConfigFormat) -> (Option<Config>, Diagnostics) {
+    let mut diagnostics = Diagnostics::default();
+
+    let result = match format {
+        ConfigFormat::Json => deserialize(
+            &mut serde_json::Deserializer::from_str(caption),
+            &mut diagnostics,
+        )
+        .map_err(|e| describe_error(&e, e.inner().to_string())),
+        ConfigFormat::Toml => deserialize(toml::Deserializer::new(caption), &mut diagnostics)
+            .map_err(|e| {
+                let message = e.inner().message().to_string();
+                match e.inner().span() {
+                    Some(span) => {
+                        let (line, column) = line_column(caption, span.start);
+                        describe_error(&e, format!("{message} at line {line} column {column}"))
+                    }
+                    None => describe_error(&e, message),
+                }
+            }),
+        ConfigFormat::Yaml => deserialize(
+            serde_norway::Deserializer::from_str(caption),
+            &mut diagnostics,
+        )
+        .map_err(|e| describe_error(&e, e.inner().to_string())),
+    };
+
+    let file = match result {
+        Ok(file) => file,
+        Err(e) => {
+            diagnostics.errors.push(e);
+            return (None, diagnostics);
+        }
+    };
+
+    let config = file.into_config(&mut diagnostics);
+    if diagnostics.is_valid() {
+        (Some(config), diagnostics)
+    } else {
+        (None, diagnostics)
+    }
+}
+
+fn deserialize<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+    diagnostics: &mut Diagnostics,
+) -> Result<ConfigFile, serde_path_to_error::Error<D::Error>> {
+    let mut track = |path: serde_ignored::Path| {
+        diagnostics
+            .warnings
+            .push(format!("{path}: unknown field is ignored"));
+    };
+    serde_path_to_error::deserialize(serde_ignored::Deserializer::new(deserializer, &mut track))
+}
+
+fn describe_error<E>(e: &serde_path_to_error::Error<E>, message: String) -> String {
+    let path = e.path().to_string();
+    // some formats already prefix their messages with the path
+    if path == "." || message.starts_with(&path) {
+        message
+    } else {
+        format!("{path}: {message}")
+    }
+}
+
+fn line_column(caption: &str, offset: usize) -> (usize, usize) {
+    let before = &caption[..offset.min(caption.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, column)
+}
+
+impl ConfigFile {
+    fn into_config(self, diagnostics: &mut Diagnostics) -> Config {
+        let order = OrderBy::from_str(&self.rwal.order_by).unwrap_or_else(|_| {
+            diagnostics.errors.push(format!(
+                "rwal.order_by: unknown order `{}`, expected one of h, s, v, sem",
+                self.rwal.order_by
+            ));
+            OrderBy::Hue
+        });
+
+        if !RESIZE_ALGORITHMS.contains(&self.resize_algorithm.as_str()) {
+            diagnostics.errors.push(format!(
+                "resize_algorithm: unknown algorithm `{}`, expected one of {}",
+                self.resize_algorithm,
+                RESIZE_ALGORITHMS.join(", ")
+            ));
+        }
+
+        if self.max_heavy_jobs == Some(0) {
+            diagnostics
+                .errors
+                .push("max_heavy_jobs: must be at least 1".to_string());
+        }
+
+        let worker_memory = self.worker_settings(diagnostics);
+        let (cache_max_size, cache_max_age) = self.cache_settings(diagnostics);
+
+        if self.rwal.accent_color >= self.rwal.rwal_colors {
+            diagnostics.warnings.push(format!(
+                "rwal.accent_color: {} is out of range for {} colors",
+                self.rwal.accent_color, self.rwal.rwal_colors
+            ));
+        }
+
+        self.check_displays(diagnostics);
+        self.check_profiles(diagnostics);
+
+        // missing templates are kept, so they are watched and rendered once they are created
+        let mut templates = Vec::new();
+        for (i, template) in self.templates.iter().enumerate() {
+            let path = expand_user(template);
+            if !Path::new(&path).exists() {
+                diagnostics
+                    .warnings
+                    .push(format!("templates[{i}]: `{template}` does not exist yet"));
+            }
+            templates.push(path);
+        }
+
+        Config {
+            displays: self.displays.clone(),
+            configured_displays: self.displays,
+            display_provider: self.display_provider,
+            display_command: self.display_command,
+            hotplug: Some(self.hotplug),
+            hotplug_socket: self.hotplug_socket.map(|path| expand_user(&path)),
+            profiles: Some(self.profiles),
+            profile: self.profile,
+            active_profile: None,
+            templates: Some(templates),
+            set_command: self.wall_command,
+            backend: self.backend,
+            display_options: Some(self.display_options),
+            fit: Some(self.fit),
+            background: Some(self.background),
+            gravity: Some(self.gravity),
+            focal_point: self.focal_point,
+            resize_algorithm: Some(self.resize_algorithm),
+            last_call_file: self.last_call_file.map(|path| expand_user(&path)),
+            rwal_params: Some(RwalParams::new(
+                (self.rwal.thumb_w, self.rwal.thumb_h),
+                (self.rwal.clamp_min, self.rwal.clamp_max),
+                self.rwal.accent_color,
+                self.rwal.rwal_colors,
+                order,
+            )),
+            image_operations: Some(ImageOperations::new(
+                self.impg.contrast,
+                self.impg.brightness,
+                self.impg.huerotate,
+                self.impg.blur,
+                self.impg.invert,
+                self.impg.flip_h,
+                self.impg.flip_v,
+            )),
+            max_heavy_jobs: self.max_heavy_jobs,
+            workers: self.workers,
+            worker_memory,
+            restore_state: Some(self.restore_state),
+            rerender_on_template_change: Some(self.rerender_on_template_change),
+            cache_max_size,
+            cache_max_age,
+            cache_encoding: Some(Encoding {
+                format: self.cache_format,
+                quality: self.cache_quality.unwrap_or(DEFAULT_QUALITY),
+                compression: self.cache_compression.unwrap_or_default(),
+            }),
+            socket_path: self.socket_path.map(|path| expand_user(&path)),
+            cache_dir: self.cache_dir.map(|path| expand_user(&path)),
+        }
+    }
+
+    // the worker pool, only its memory budget is parsed
+    fn worker_settings(&self, diagnostics: &mut Diagnostics) -> Option<u64> {
+        if self.workers == Some(0) {
+            diagnostics
+                .errors
+                .push("workers: must be at least 1".to_string());
+        }
+        self.worker_memory.as_deref().and_then(|size| {
+            let parsed = parse_size(size).filter(|size| *size > 0);
+            if parsed.is_none() {
+                diagnostics.errors.push(format!(
+                    "worker_memory: `{size}` is not a size, expected a number with an optional K, M or G suffix"
+                ));
+            }
+            parsed
+        })
+    }
+
+    // budget of the cache, the encoding is only checked
+    fn cache_settings(&self, diagnostics: &mut Diagnostics) -> (Option<u64>, Option<u64>) {
+        let cache_max_size = self.cache_max_size.as_deref().and_then(|size| {
+            let parsed = parse_size(size).filter(|size| *size > 0);
+            if parsed.is_none() {
+                diagnostics.errors.push(format!(
+                    "cache_max_size: `{size}` is not a size, expected a number with an optional K, M or G suffix"
+                ));
+            }
+            parsed
+        });
+        let cache_max_age = self.cache_max_age.as_deref().and_then(|age| {
+            let parsed = parse_interval(age).filter(|age| *age > 0);
+            if parsed.is_none() {
+                diagnostics.errors.push(format!(
+                    "cache_max_age: `{age}` is not a duration, expected a number with an optional s, m, h or d suffix"
+                ));
+            }
+            parsed
+        });
+
+        if self
+            .cache_quality
+            .is_some_and(|quality| !(1..=100).contains(&quality))
+        {
+            diagnostics
+                .errors
+                .push("cache_quality: must be between 1 and 100".to_string());
+        }
+        // the original format may be either, so both are accepted with it
+        if self.cache_quality.is_some()
+            && matches!(self.cache_format, CacheFormat::Png | CacheFormat::Webp)
+        {
+            diagnostics
+                .errors
+                .push("cache_quality: only jpeg is lossy, png and webp ignore it".to_string());
+        }
+        if self.cache_compression.is_some()
+            && matches!(self.cache_format, CacheFormat::Jpeg | CacheFormat::Webp)
+        {
+            diagnostics.errors.push(
+                "cache_compression: only png is compressed, jpeg and webp ignore it".to_string(),
+            );
+        }
+        (cache_max_size, cache_max_age)
+    }
+
+    // geometry of the displays and the profiles they are grouped in
+    fn check_profiles(&self, diagnostics: &mut Diagnostics) {
+        let profile_displays = self.profiles.iter().flat_map(|(name, profile)| {
+            profile
+                .displays
+                .iter()
+                .map(move |d| (format!("profiles.{name}."), d))
+        });
+        for (prefix, display) in self
+            .displays
+            .iter()
+            .flatten()
+            .map(|display| (String::new(), display))
+            .chain(profile_displays)
+        {
+            if display.scale <= 0.0 {
+                diagnostics.errors.push(format!(
+                    "{prefix}displays.{}.scale: scale has to be greater than 0",
+                    display.name
+                ));
+            }
+        }
+        for (name, profile) in &self.profiles {
+            if name == "auto" {
+                diagnostics.errors.push(
+                    "profiles.auto: the name is reserved for matching the connected displays"
+                        .to_string(),
+                );
+            }
+            if profile.displays.is_empty() {
+                diagnostics.errors.push(format!(
+                    "profiles.{name}.displays: a profile needs at least one display"
+                ));
+            }
+        }
+        if let Some(profile) = &self.profile {
+            if !self.profiles.contains_key(profile) {
+                diagnostics
+                    .errors
+                    .push(format!("profile: there is no profile named `{profile}`"));
+            }
+        }
+    }
+
+    // displays, their backends and wallpaper placement
+    fn check_displays(&self, diagnostics: &mut Diagnostics) {
+        if self.displays.as_ref().map_or(true, Vec::is_empty)
+            && self.display_provider.is_none()
+            && self.profiles.is_empty()
+        {
+            diagnostics.warnings.push(
+                "displays: no displays configured, they will be detected from the compositor"
+                    .to_string(),
+            );
+        }
+        if self.display_command.is_some()
+            && matches!(self.display_provider, None | Some(ProviderKind::Auto))
+        {
+            diagnostics.errors.push(
+                "display_command: display_provider has to name the format of its output"
+                    .to_string(),
+            );
+        }
+        if self.hotplug_socket.is_some()
+            && !matches!(
+                self.display_provider,
+                Some(ProviderKind::Sway | ProviderKind::Hyprland)
+            )
+        {
+            diagnostics.errors.push(
+                "hotplug_socket: display_provider has to be sway or hyprland to know its protocol"
+                    .to_string(),
+            );
+        }
+
+        if self.wall_command.is_none() && self.backend.is_none() {
+            diagnostics.warnings.push(
+                "backend: neither backend nor wall_command is set, wallpapers will only be cached"
+                    .to_string(),
+            );
+        }
+        if self.wall_command.is_none()
+            && (self.backend == Some(BackendKind::Command)
+                || self
+                    .display_options
+                    .values()
+                    .any(|options| options.backend == Some(BackendKind::Command)))
+        {
+            diagnostics
+                .errors
+                .push("wall_command: required by the command backend".to_string());
+        }
+        // detected displays are only known at runtime
+        let detected = self.display_provider.is_some()
+            || (self.displays.as_ref().map_or(true, Vec::is_empty) && self.profiles.is_empty());
+        let known = |name: &String| {
+            self.displays
+                .iter()
+                .flatten()
+                .chain(self.profiles.values().flat_map(|profile| &profile.displays))
+                .any(|display| &display.name == name)
+        };
+        for name in self.display_options.keys() {
+            if !detected && !known(name) {
+                diagnostics.warnings.push(format!(
+                    "display_options.{name}: there is no display with this name"
+                ));
+            }
+        }
+
+        let focal_points = self
+            .focal_point
+            .iter()
+            .map(|point| ("focal_point".to_string(), point));
+        let display_focal_points = self.display_options.iter().filter_map(|(name, options)| {
+            options
+                .focal_point
+                .as_ref()
+                .map(|point| (format!("display_options.{name}.focal_point"), point))
+        });
+        for (key, point) in focal_points.chain(display_focal_points) {
+            if point.iter().any(|value| !(0.0..=1.0).contains(value)) {
+                diagnostics.errors.push(format!(
+                    "{key}: coordinates are fractions of the image size between 0 and 1"
+                ));
+            }
+        }
+    }
+}
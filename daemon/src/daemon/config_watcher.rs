@@ -1,32 +1,152 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{self, Read};
-use std::sync::mpsc;
-use std::thread;
-use std::time::Duration;
+use std::os::fd::{AsFd, AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
 
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor};
 use sha2::{Digest, Sha256};
 
-use crate::daemon::daemon::MpscData;
-
-pub fn start_config_watcher(config_path: &str, sender: mpsc::Sender<MpscData>) {
-    let config_path = String::from(config_path);
-    let _ = thread::Builder::new()
-        .name("config watcher thread".to_string())
-        .spawn(move || {
-            if let Ok(file_caption) = read_file(&config_path) {
-                let mut hash = file_caption.hash;
-                loop {
-                    if let Ok(file_caption) = read_file(&config_path) {
-                        if file_caption.hash != hash {
-                            hash = file_caption.hash;
-                            let _ = sender.send(MpscData::ConfigChanged(file_caption.caption));
-                        }
+use crate::daemon::config::Config;
+use crate::logger::logger::{info, warn};
+use crate::paths;
+use crate::template::parser::collect_includes;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Config,
+    Template,
+}
+
+// files are watched through their parent directories, so editors which write a temporary
+// file and rename it over the original, and files created or removed later are noticed as well
+pub struct ConfigWatcher {
+    inotify: Inotify,
+    config_path: String,
+    hash: Option<String>,
+    directories: HashMap<WatchDescriptor, PathBuf>,
+    files: HashMap<PathBuf, Change>,
+}
+
+impl ConfigWatcher {
+    pub fn new(config: &Config) -> Result<Self, String> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)
+            .map_err(|e| format!("unable to initialize inotify: {e}"))?;
+
+        let config_path = paths().config_path();
+        let mut watcher = ConfigWatcher {
+            inotify,
+            hash: read_file(&config_path).ok().map(|caption| caption.hash),
+            config_path,
+            directories: HashMap::new(),
+            files: HashMap::new(),
+        };
+        watcher.update_watches(config);
+
+        Ok(watcher)
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.inotify.as_fd().as_raw_fd()
+    }
+
+    // called after every config reload because templates and their includes may change
+    pub fn update_watches(&mut self, config: &Config) {
+        let mut files = HashMap::new();
+        // every candidate is watched, so creating another config format is noticed as well
+        for candidate in paths().config_candidates() {
+            files.insert(PathBuf::from(candidate), Change::Config);
+        }
+        for template in config.templates.iter().flatten() {
+            files.insert(PathBuf::from(template), Change::Template);
+            for include in collect_includes(template) {
+                files.insert(PathBuf::from(include), Change::Template);
+            }
+        }
+
+        let mut needed: Vec<PathBuf> = Vec::new();
+        for file in files.keys() {
+            if let Some(parent) = file.parent() {
+                if !needed.iter().any(|dir| dir == parent) {
+                    needed.push(parent.to_path_buf());
+                }
+            }
+        }
+
+        let unused: Vec<WatchDescriptor> = self
+            .directories
+            .iter()
+            .filter(|(_, dir)| !needed.contains(dir))
+            .map(|(wd, _)| *wd)
+            .collect();
+        for wd in unused {
+            let _ = self.inotify.rm_watch(wd);
+            self.directories.remove(&wd);
+        }
+
+        for dir in needed {
+            if self.directories.values().any(|watched| *watched == dir) {
+                continue;
+            }
+            match self.inotify.add_watch(
+                &dir,
+                AddWatchFlags::IN_CLOSE_WRITE
+                    | AddWatchFlags::IN_MODIFY
+                    | AddWatchFlags::IN_CREATE
+                    | AddWatchFlags::IN_MOVED_TO
+                    | AddWatchFlags::IN_DELETE
+                    | AddWatchFlags::IN_MOVED_FROM,
+            ) {
+                Ok(wd) => {
+                    self.directories.insert(wd, dir);
+                }
+                Err(e) => warn(&format!("Unable to watch {}: {e}", dir.display())),
+            }
+        }
+
+        self.files = files;
+    }
+
+    pub fn read_changes(&self) -> Vec<Change> {
+        let mut changes = Vec::new();
+
+        while let Ok(events) = self.inotify.read_events() {
+            for event in events {
+                let (Some(dir), Some(name)) = (self.directories.get(&event.wd), event.name) else {
+                    continue;
+                };
+                if let Some(change) = self.files.get(&dir.join(name)) {
+                    if !changes.contains(change) {
+                        changes.push(*change);
                     }
-                    thread::sleep(Duration::from_millis(100));
                 }
             }
-            panic!("CANNOT FIND CONFIG FILE AT {}", config_path)
-        });
+        }
+
+        changes
+    }
+
+    pub fn config_path(&self) -> &str {
+        &self.config_path
+    }
+
+    // returns the new caption when the config content changed since the last read
+    pub fn read_config(&mut self) -> Option<String> {
+        let config_path = paths().config_path();
+        if config_path != self.config_path {
+            info(&format!("Using config file {config_path}."));
+            self.config_path = config_path;
+            self.hash = None;
+        }
+
+        let file_caption = read_file(&self.config_path).ok()?;
+        if self.hash.as_ref() == Some(&file_caption.hash) {
+            return None;
+        }
+        self.hash = Some(file_caption.hash);
+
+        Some(file_caption.caption)
+    }
 }
 
 struct FileCaption {
@@ -34,7 +154,7 @@ struct FileCaption {
     caption: String,
 }
 
-fn read_file(path: &str) -> Result<FileCaption, io::Error> {
+fn read_file(path: impl AsRef<Path>) -> Result<FileCaption, io::Error> {
     let mut hasher = Sha256::new();
     let mut file = File::open(path)?;
     let mut buffer = Vec::new();
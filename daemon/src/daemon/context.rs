@@ -0,0 +1,22 @@
+use std::sync::{Arc, RwLock};
+
+use crate::daemon::config::Config;
+use crate::daemon::jobs::JobQueue;
+use crate::daemon::limiter::JobLimiter;
+use crate::daemon::notifier::Notifier;
+use crate::daemon::rotation::Rotations;
+
+#[derive(Clone)]
+pub struct Context {
+    pub config: Arc<RwLock<Config>>,
+    pub limiter: Arc<JobLimiter>,
+    pub jobs: Arc<JobQueue>,
+    pub rotations: Arc<Rotations>,
+    pub notifier: Notifier,
+}
+
+impl Context {
+    pub fn config(&self) -> Config {
+        self.config.read().unwrap().clone()
+    }
+}
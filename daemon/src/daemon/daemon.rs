@@ -1,53 +1,143 @@
-use std::path::Path;
-use std::sync::mpsc;
-use std::thread;
-use std::time::Duration;
+use std::fs;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::sync::{mpsc, Arc, RwLock};
+use std::time::{Duration, Instant};
 
+use mio::net::UnixListener;
+use mio::unix::SourceFd;
+use mio::{Events, Interest, Poll, Token, Waker};
+use nix::sys::signal::{SigSet, Signal};
+use nix::sys::signalfd::{SfdFlags, SignalFd};
+
+use crate::cache::{manifest, source};
+use crate::colorscheme::scheme::apply_templates;
+use crate::daemon::config_watcher::{Change, ConfigWatcher};
+use crate::daemon::context::Context;
+use crate::daemon::directory_watcher::ensure_directories;
+use crate::daemon::displays::{reapply_wallpaper_in_background, refresh_displays};
+use crate::daemon::events::emit;
+use crate::daemon::hotplug::HotplugWatcher;
+use crate::daemon::jobs::JobQueue;
+use crate::daemon::limiter::{JobLimiter, DEFAULT_HEAVY_JOBS};
+use crate::daemon::pool::{default_workers, pool, Priority, DEFAULT_WORKER_MEMORY};
+use crate::daemon::notifier::Notifier;
 use crate::daemon::request::RequestHandler;
+use crate::daemon::rotation::Rotations;
+use crate::daemon::state::{self, current_state};
 use crate::logger::logger::{err, info, log, warn};
-use crate::{daemon::config::Config, expand_user, CONFIG_PATH};
-use crate::{unix_timestamp, CACHE_DIR, COLORS_DIR, CONFIG_DIR, SOCKET_PATH, WALLPAPERS_DIR};
+use crate::wallpaper::backend::{adopt_setters, kill_adopted_setters, kill_setters};
+use crate::wallpaper::provider::resolve_displays;
+use crate::daemon::config::Config;
+use crate::{paths, unix_timestamp};
+use common::paths::ConfigFormat;
+use common::Event;
+
+use super::socket_listener::{accept_clients, bind_socket};
+
+const SOCKET: Token = Token(0);
+const WAKER: Token = Token(1);
+const SIGNALS: Token = Token(2);
+const WATCHER: Token = Token(3);
 
-use super::config_watcher::start_config_watcher;
-use super::directory_watcher::start_directory_watcher;
-use super::socket_listener::start_socket_listener;
+// editors often save in several steps, changes are handled once the files settle
+const DEBOUNCE_INTERVAL: Duration = Duration::from_millis(200);
+// a plugged in monitor is reported several times while the compositor configures it
+const HOTPLUG_INTERVAL: Duration = Duration::from_millis(500);
 
-#[derive(Clone)]
 pub enum MpscData {
-    ConfigChanged(String),
-    ErrorCreatingDirectory,
-    SuccesCreatingDirectory,
-    SocketRequest(String),
-    Respond(RequestHandler),
+    Shutdown,
+    JobFinished(u64),
+    OutputsChanged,
+}
+
+#[derive(Clone, PartialEq, Eq)]
+enum Timer {
+    ReloadConfig,
+    RerenderTemplates,
+    RefreshDisplays,
 }
 
 pub struct Daemon {
-    config: Config,
+    context: Context,
     receiver: mpsc::Receiver<MpscData>,
-    socket_sender: mpsc::Sender<MpscData>,
+    poll: Poll,
+    listener: UnixListener,
+    signals: SignalFd,
+    config_watcher: Option<ConfigWatcher>,
+    hotplug: Option<HotplugWatcher>,
+    timers: Vec<(Instant, Timer)>,
+    running: bool,
 }
 
 impl Daemon {
-    pub fn new(init_path: Option<String>) -> Self {
+    pub fn new(init_path: Option<String>, restore: bool) -> Self {
         let timestamp = unix_timestamp();
-        let (sender, receiver) = mpsc::channel();
 
-        let mut config = Config::new();
-        config.read(&expand_user(CONFIG_PATH));
-
-        start_directory_watcher(
-            vec![
-                expand_user(CACHE_DIR),
-                expand_user(WALLPAPERS_DIR),
-                expand_user(COLORS_DIR),
-                expand_user(CONFIG_DIR),
-            ],
-            sender.clone(),
+        // signals are blocked before any thread is spawned so all of them inherit the mask
+        // and termination requests are only delivered through the signalfd
+        let mut mask = SigSet::empty();
+        mask.add(Signal::SIGTERM);
+        mask.add(Signal::SIGINT);
+        mask.thread_block()
+            .unwrap_or_else(|e| panic!("Unable to block signals: {e}"));
+        let signals = SignalFd::with_flags(&mask, SfdFlags::SFD_NONBLOCK)
+            .unwrap_or_else(|e| panic!("Unable to create signalfd: {e}"));
+
+        let poll = Poll::new().unwrap_or_else(|e| panic!("Unable to create poll: {e}"));
+        let waker = Arc::new(
+            Waker::new(poll.registry(), WAKER)
+                .unwrap_or_else(|e| panic!("Unable to create waker: {e}")),
         );
+        let (sender, receiver) = mpsc::channel();
+        let notifier = Notifier::new(sender, waker);
 
-        start_config_watcher(&expand_user(CONFIG_PATH), sender.clone());
+        ensure_directories(&needed_directories());
 
-        let socket_sender = start_socket_listener(SOCKET_PATH, sender.clone());
+        let mut config = load_config(&paths().config_path());
+        resolve_displays(&mut config, current_state().profile.as_deref());
+
+        let config_watcher = match ConfigWatcher::new(&config) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                err(&format!("Config changes will not be picked up: {e}"));
+                None
+            }
+        };
+
+        adopt_setters();
+        manifest::enforce(&config);
+        configure_pool(&config);
+
+        let limiter = Arc::new(JobLimiter::new(
+            config.max_heavy_jobs.unwrap_or(DEFAULT_HEAVY_JOBS),
+        ));
+        let config = Arc::new(RwLock::new(config));
+        let context = Context {
+            config: config.clone(),
+            limiter: limiter.clone(),
+            jobs: JobQueue::start(notifier.clone()),
+            rotations: Rotations::start(config, limiter, current_state().rotations),
+            notifier,
+        };
+        let hotplug = HotplugWatcher::start(&context.config(), context.notifier.clone());
+
+        let mut listener = bind_socket(&paths().socket);
+        poll.registry()
+            .register(&mut listener, SOCKET, Interest::READABLE)
+            .unwrap_or_else(|e| panic!("Unable to register socket: {e}"));
+        poll.registry()
+            .register(
+                &mut SourceFd(&signals.as_raw_fd()),
+                SIGNALS,
+                Interest::READABLE,
+            )
+            .unwrap_or_else(|e| panic!("Unable to register signalfd: {e}"));
+        if let Some(watcher) = &config_watcher {
+            poll.registry()
+                .register(&mut SourceFd(&watcher.fd()), WATCHER, Interest::READABLE)
+                .unwrap_or_else(|e| panic!("Unable to register inotify: {e}"));
+        }
 
         info(&format!(
             "Daemon initialized in {}ms.",
@@ -57,43 +147,222 @@ impl Daemon {
         if let Some(init_path) = init_path {
             log("processing init_path");
             if let Ok(message) = std::fs::read_to_string(init_path) {
-                let mut request_handler = RequestHandler::new(config.clone(), message);
+                let mut request_handler =
+                    RequestHandler::new(context.config(), message, context.clone());
                 request_handler.handle();
             } else {
                 warn("init path does not exist");
             }
+        } else if restore || context.config().restore_state.unwrap_or(false) {
+            log("restoring last state");
+            if let Err(e) = state::restore(&context.config()) {
+                warn(&format!("Unable to restore state: {e}"));
+            }
         }
+        kill_adopted_setters();
 
         Daemon {
-            config,
+            context,
             receiver,
-            socket_sender,
+            poll,
+            listener,
+            signals,
+            config_watcher,
+            hotplug,
+            timers: Vec::new(),
+            running: true,
         }
     }
 
     pub fn mainloop(&mut self) {
-        while Path::new(SOCKET_PATH).exists() {
-            if let Ok(received_data) = self.receiver.try_recv() {
-                match received_data {
-                    MpscData::ConfigChanged(value) => {
-                        self.config.read_from_string(value);
-                        info("Config changed.");
-                    }
-                    MpscData::ErrorCreatingDirectory => {
-                        err("Unable to create needed directory.");
-                    }
-                    MpscData::SuccesCreatingDirectory => {
-                        info("Needed directory created.");
-                    }
-                    MpscData::SocketRequest(message) => {
-                        log("Received socket request.");
-                        let handler = RequestHandler::new(self.config.clone(), message);
-                        let _ = self.socket_sender.send(MpscData::Respond(handler));
+        let mut events = Events::with_capacity(64);
+
+        while self.running {
+            if let Err(e) = self.poll.poll(&mut events, self.next_timeout()) {
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                err(&format!("Unable to poll events: {e}"));
+                break;
+            }
+
+            for event in &events {
+                match event.token() {
+                    SOCKET => {
+                        ensure_directories(&needed_directories());
+                        accept_clients(&self.listener, &self.context);
                     }
+                    SIGNALS => self.handle_signals(),
+                    WAKER => self.handle_messages(),
+                    WATCHER => self.handle_file_changes(),
                     _ => {}
                 }
             }
-            thread::sleep(Duration::from_millis(10));
+
+            self.run_timers();
+        }
+
+        info("Shutting down.");
+        kill_setters();
+        manifest::flush();
+        source::flush();
+        let _ = std::fs::remove_file(&paths().socket);
+    }
+
+    fn handle_signals(&mut self) {
+        while let Ok(Some(siginfo)) = self.signals.read_signal() {
+            if let Ok(signal) = Signal::try_from(i32::try_from(siginfo.ssi_signo).unwrap_or_default()) {
+                info(&format!("Received {signal}."));
+            }
+            self.running = false;
+        }
+    }
+
+    fn handle_messages(&mut self) {
+        while let Ok(received_data) = self.receiver.try_recv() {
+            match received_data {
+                MpscData::Shutdown => self.running = false,
+                MpscData::JobFinished(id) => info(&format!("Job {id} finished.")),
+                MpscData::OutputsChanged => self.schedule(Timer::RefreshDisplays, HOTPLUG_INTERVAL),
+            }
+        }
+    }
+
+    fn handle_file_changes(&mut self) {
+        let Some(watcher) = &self.config_watcher else {
+            return;
+        };
+        for change in watcher.read_changes() {
+            match change {
+                Change::Config => self.schedule(Timer::ReloadConfig, DEBOUNCE_INTERVAL),
+                Change::Template => self.schedule(Timer::RerenderTemplates, DEBOUNCE_INTERVAL),
+            }
+        }
+    }
+
+    fn schedule(&mut self, timer: Timer, after: Duration) {
+        self.timers.retain(|(_, t)| *t != timer);
+        self.timers.push((Instant::now() + after, timer));
+    }
+
+    fn next_timeout(&self) -> Option<Duration> {
+        let now = Instant::now();
+        self.timers
+            .iter()
+            .map(|(deadline, _)| deadline.saturating_duration_since(now))
+            .min()
+    }
+
+    fn run_timers(&mut self) {
+        let now = Instant::now();
+        let (due, pending) = self
+            .timers
+            .drain(..)
+            .partition(|(deadline, _)| *deadline <= now);
+        self.timers = pending;
+
+        for (_, timer) in due {
+            match timer {
+                Timer::ReloadConfig => self.reload_config(),
+                Timer::RerenderTemplates => self.rerender_templates(),
+                Timer::RefreshDisplays => self.refresh_displays(),
+            }
+        }
+    }
+
+    fn reload_config(&mut self) {
+        let Some(watcher) = &mut self.config_watcher else {
+            return;
+        };
+        let Some(caption) = watcher.read_config() else {
+            return;
+        };
+
+        let config_path = watcher.config_path();
+        let (config, diagnostics) =
+            Config::from_string(&caption, ConfigFormat::from_path(config_path));
+        diagnostics.log(config_path);
+        let Some(config) = config else {
+            err("Config is invalid, keeping the last good config.");
+            return;
+        };
+
+        let changed = refresh_displays(&self.context, config);
+        let config = self.context.config();
+        self.context
+            .limiter
+            .set_limit(config.max_heavy_jobs.unwrap_or(DEFAULT_HEAVY_JOBS));
+        manifest::enforce(&config);
+        configure_pool(&config);
+        watcher.update_watches(&config);
+        if !HotplugWatcher::is_current(self.hotplug.as_ref(), &config) {
+            self.hotplug = HotplugWatcher::start(&config, self.context.notifier.clone());
+        }
+        info("Config changed.");
+        emit(&Event::ConfigReloaded);
+        if changed {
+            reapply_wallpaper_in_background(&self.context);
         }
     }
+
+    fn refresh_displays(&mut self) {
+        info("Outputs changed, detecting displays.");
+        if refresh_displays(&self.context, self.context.config()) {
+            reapply_wallpaper_in_background(&self.context);
+        }
+    }
+
+    fn rerender_templates(&mut self) {
+        let config = self.context.config();
+        // includes may have been added or removed
+        if let Some(watcher) = &mut self.config_watcher {
+            watcher.update_watches(&config);
+        }
+        if !config.rerender_on_template_change.unwrap_or(false) {
+            return;
+        }
+        let (Some(templates), palette) = (config.templates, current_state().palette) else {
+            return;
+        };
+        if palette.is_empty() {
+            return;
+        }
+
+        info("Template changed, applying current palette.");
+        // templates may run commands, so they are applied outside of the main loop
+        pool().spawn(Priority::Background, move || {
+            apply_templates(&templates, &palette);
+        });
+    }
+}
+
+fn load_config(path: &str) -> Config {
+    let Ok(caption) = fs::read_to_string(path) else {
+        warn(&format!("Config file {path} does not exist, using defaults."));
+        return Config::new();
+    };
+
+    info(&format!("Using config file {path}."));
+    let (config, diagnostics) = Config::from_string(&caption, ConfigFormat::from_path(path));
+    diagnostics.log(path);
+    config.unwrap_or_else(|| {
+        err("Config is invalid, using defaults.");
+        Config::new()
+    })
+}
+
+fn needed_directories() -> Vec<String> {
+    vec![
+        paths().cache_dir.clone(),
+        paths().wallpapers_dir(),
+        paths().colors_dir(),
+        paths().config_dir.clone(),
+    ]
+}
+
+fn configure_pool(config: &Config) {
+    pool().configure(
+        config.workers.unwrap_or_else(default_workers),
+        config.worker_memory.unwrap_or(DEFAULT_WORKER_MEMORY),
+    );
 }
@@ -1,28 +1,16 @@
 use std::fs;
 use std::path::Path;
-use std::sync::mpsc;
-use std::thread;
-use std::time::Duration;
 
-use crate::daemon::daemon::MpscData;
+use crate::logger::logger::{err, info};
 
-pub fn start_directory_watcher(directories: Vec<String>, sender: mpsc::Sender<MpscData>) {
-    let _ = thread::Builder::new()
-        .name("directory watcher thread".to_string())
-        .spawn(move || loop {
-            for dir in directories.iter() {
-                let path = Path::new(&dir);
-                if !path.exists() {
-                    match fs::create_dir(path) {
-                        Ok(_) => {
-                            let _ = sender.send(MpscData::SuccesCreatingDirectory);
-                        }
-                        Err(_) => {
-                            let _ = sender.send(MpscData::ErrorCreatingDirectory);
-                        }
-                    }
-                }
+pub fn ensure_directories(directories: &[String]) {
+    for dir in directories {
+        let path = Path::new(&dir);
+        if !path.exists() {
+            match fs::create_dir_all(path) {
+                Ok(()) => info(&format!("Needed directory {dir} created.")),
+                Err(e) => err(&format!("Unable to create needed directory {dir}: {e}")),
             }
-            thread::sleep(Duration::from_millis(100));
-        });
+        }
+    }
 }
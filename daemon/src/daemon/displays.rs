@@ -0,0 +1,46 @@
+use crate::daemon::config::Config;
+use crate::daemon::context::Context;
+use crate::daemon::events::emit;
+use crate::daemon::pool::{pool, Priority};
+use crate::daemon::state::{self, current_state};
+use crate::logger::logger::{info, warn};
+use crate::wallpaper::provider::resolve_displays;
+use common::Event;
+
+// resolves the displays of `config` and makes it the current config,
+// returns whether the layout differs from the one used before
+pub fn refresh_displays(context: &Context, mut config: Config) -> bool {
+    resolve_displays(&mut config, current_state().profile.as_deref());
+
+    let mut current = context.config.write().unwrap();
+    let changed =
+        current.displays != config.displays || current.active_profile != config.active_profile;
+    *current = config;
+    let profile = current.active_profile.clone();
+    drop(current);
+
+    if changed {
+        match &profile {
+            Some(name) => info(&format!("Using display profile {name}.")),
+            None => info("Displays changed."),
+        }
+        emit(&Event::DisplaysChanged { profile });
+    }
+    changed
+}
+
+// the wallpaper is cropped again for the new layout, this is a heavy job like any other set
+pub fn reapply_wallpaper(context: &Context) {
+    let _permit = context.limiter.acquire();
+    if let Err(e) = state::reapply_wallpaper(&context.config()) {
+        warn(&format!(
+            "Unable to set the wallpaper for the new displays: {e}"
+        ));
+    }
+}
+
+// used from the main loop, which must not wait for the wallpaper
+pub fn reapply_wallpaper_in_background(context: &Context) {
+    let context = context.clone();
+    pool().spawn(Priority::Background, move || reapply_wallpaper(&context));
+}
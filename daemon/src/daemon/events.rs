@@ -0,0 +1,38 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::Mutex;
+
+use common::Event;
+
+static SUBSCRIBERS: Mutex<Vec<(u64, mpsc::Sender<String>)>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+// events are received until the subscription is dropped
+pub struct Subscription {
+    id: u64,
+    pub events: mpsc::Receiver<String>,
+}
+
+pub fn subscribe() -> Subscription {
+    let (sender, events) = mpsc::channel();
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    SUBSCRIBERS.lock().unwrap().push((id, sender));
+    Subscription { id, events }
+}
+
+pub fn emit(event: &Event) {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    if subscribers.is_empty() {
+        return;
+    }
+
+    if let Ok(line) = serde_json::to_string(event) {
+        subscribers.retain(|(_, subscriber)| subscriber.send(line.clone()).is_ok());
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        SUBSCRIBERS.lock().unwrap().retain(|(id, _)| *id != self.id);
+    }
+}
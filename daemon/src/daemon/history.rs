@@ -0,0 +1,235 @@
+use std::fs;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cache::store::write_atomic;
+use crate::colorscheme::rwal::rwal_params::RwalParams;
+use crate::colorscheme::scheme::set_scheme;
+use crate::daemon::config::Config;
+use crate::daemon::request::on_pool;
+use crate::daemon::state::display_images;
+use crate::logger::logger::{info, warn};
+use crate::wallpaper::display::{set_display_wallpapers, set_wallpaper};
+use crate::wallpaper::image::ImageOperations;
+use crate::{paths, unix_timestamp};
+use common::Display;
+
+const HISTORY_SIZE: usize = 50;
+
+static HISTORY: Mutex<Option<History>> = Mutex::new(None);
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct History {
+    pub entries: Vec<HistoryEntry>,
+    // the entry on screen, entries after it were left with `prev`
+    pub position: usize,
+    // counts the changes, so an entry applied outside of the lock only moves an unchanged history
+    #[serde(skip)]
+    generation: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    // the spanning image, none when every display got an image of its own
+    pub image: Option<String>,
+    pub displays: Vec<HistoryDisplay>,
+    pub image_operations: Option<ImageOperations>,
+    pub rwal_params: Option<RwalParams>,
+    pub wallpaper: bool,
+    pub colors: bool,
+    pub applied_at: u128,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HistoryDisplay {
+    pub display: Display,
+    pub image: String,
+}
+
+impl HistoryEntry {
+    // the palette of per display requests is taken from the first display
+    fn palette_image(&self) -> Option<&str> {
+        self.image
+            .as_deref()
+            .or_else(|| self.displays.first().map(|d| d.image.as_str()))
+    }
+}
+
+pub fn current_history() -> History {
+    update(|history| history.clone(), false)
+}
+
+// a new entry replaces the entries left with `prev`, like in a browser
+pub fn record(
+    config: &Config,
+    image: Option<&str>,
+    images: &[(Display, String)],
+    wallpaper: bool,
+    colors: bool,
+) {
+    let entry = HistoryEntry {
+        image: image.map(ToString::to_string),
+        displays: images
+            .iter()
+            .map(|(display, image)| HistoryDisplay {
+                display: display.clone(),
+                image: image.clone(),
+            })
+            .collect(),
+        image_operations: config.image_operations.clone(),
+        rwal_params: config.rwal_params.clone(),
+        wallpaper,
+        colors,
+        applied_at: unix_timestamp(),
+    };
+
+    update(
+        |history| {
+            if !history.entries.is_empty() {
+                history.entries.truncate(history.position + 1);
+            }
+            history.entries.push(entry);
+            if history.entries.len() > HISTORY_SIZE {
+                history.entries.remove(0);
+            }
+            history.position = history.entries.len() - 1;
+        },
+        true,
+    );
+}
+
+pub fn previous(config: &Config) -> Result<HistoryEntry, String> {
+    go_to(config, false, previous_position)
+}
+
+pub fn next(config: &Config) -> Result<HistoryEntry, String> {
+    go_to(config, false, |history| {
+        Some(history.position + 1)
+            .filter(|position| *position < history.entries.len())
+            .ok_or_else(|| "there is no next wallpaper in the history".to_string())
+    })
+}
+
+// like `previous`, but the undone entry is dropped and can not be returned to with `next`
+pub fn undo(config: &Config) -> Result<HistoryEntry, String> {
+    go_to(config, true, previous_position)
+}
+
+fn previous_position(history: &History) -> Result<usize, String> {
+    history
+        .position
+        .checked_sub(1)
+        .filter(|position| *position < history.entries.len())
+        .ok_or_else(|| "there is no previous wallpaper in the history".to_string())
+}
+
+// the entry is applied without holding the lock, requests keep recording meanwhile. the
+// position is moved only when nothing was recorded in between
+fn go_to(
+    config: &Config,
+    undo: bool,
+    position: impl FnOnce(&History) -> Result<usize, String>,
+) -> Result<HistoryEntry, String> {
+    let (position, entry, generation) = update(
+        |history| {
+            let position = position(history)?;
+            Ok::<_, String>((
+                position,
+                history.entries[position].clone(),
+                history.generation,
+            ))
+        },
+        false,
+    )?;
+
+    apply(config, &entry)?;
+
+    update(
+        |history| {
+            if history.generation != generation {
+                warn("The history changed while an entry was set, its position is kept.");
+                return;
+            }
+            history.position = position;
+            if undo {
+                history.entries.remove(position + 1);
+            }
+        },
+        true,
+    );
+    Ok(entry)
+}
+
+// cached crops and palettes of the entry are used when they still exist
+fn apply(config: &Config, entry: &HistoryEntry) -> Result<(), String> {
+    let image_paths: Vec<&str> = entry
+        .image
+        .iter()
+        .chain(entry.displays.iter().map(|saved| &saved.image))
+        .map(String::as_str)
+        .collect();
+    let (owned_config, owned_entry) = (config.clone(), entry.clone());
+    on_pool(config, &image_paths, move || {
+        apply_entry(owned_config, &owned_entry)
+    })
+}
+
+fn apply_entry(mut config: Config, entry: &HistoryEntry) -> Result<(), String> {
+    if entry.image_operations.is_some() {
+        config.image_operations.clone_from(&entry.image_operations);
+    }
+    if entry.rwal_params.is_some() {
+        config.rwal_params.clone_from(&entry.rwal_params);
+    }
+
+    if entry.wallpaper {
+        if let Some(image) = &entry.image {
+            info(&format!("Setting {image} from the history."));
+            set_wallpaper(&config, image)?;
+        } else {
+            info("Setting wallpapers of every display from the history.");
+            let saved: Vec<(Display, String)> = entry
+                .displays
+                .iter()
+                .map(|saved| (saved.display.clone(), saved.image.clone()))
+                .collect();
+            set_display_wallpapers(&config, &display_images(&config, &saved))?;
+        }
+    }
+    if entry.colors {
+        if let Some(image) = entry.palette_image() {
+            set_scheme(&config, image)?;
+        }
+    }
+    Ok(())
+}
+
+fn update<T>(f: impl FnOnce(&mut History) -> T, save_history: bool) -> T {
+    let mut guard = HISTORY.lock().unwrap();
+    let history = guard.get_or_insert_with(load);
+    let result = f(history);
+    if save_history {
+        history.generation += 1;
+        save(history);
+    }
+    result
+}
+
+fn save(history: &History) {
+    if let Ok(data) = serde_json::to_string_pretty(history) {
+        if let Err(e) = write_atomic(&paths().history_path(), |temp_path| {
+            fs::write(temp_path, data).map_err(|e| e.to_string())
+        }) {
+            warn(&format!("Unable to save wallpaper history: {e}"));
+        }
+    }
+}
+
+fn load() -> History {
+    fs::read_to_string(paths().history_path())
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
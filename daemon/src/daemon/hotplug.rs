@@ -0,0 +1,443 @@
+use std::env;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::daemon::config::Config;
+use crate::daemon::daemon::MpscData;
+use crate::daemon::notifier::Notifier;
+use crate::logger::logger::{info, warn};
+use crate::wallpaper::provider::{detect_kind, ProviderKind};
+
+// how often the thread checks whether it should stop while waiting for events
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// the compositor may restart, the socket is connected again after a while
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+const SWAY_MAGIC: &[u8] = b"i3-ipc";
+const SWAY_HEADER: usize = 14;
+// events of a few outputs are far smaller, a longer payload is not buffered
+const SWAY_MAX_PAYLOAD: usize = 1 << 20;
+const SWAY_SUBSCRIBE: u32 = 2;
+const SWAY_OUTPUT_EVENT: u32 = 0x8000_0007;
+const HYPRLAND_EVENTS: [&str; 5] = [
+    "monitoradded",
+    "monitoraddedv2",
+    "monitorremoved",
+    "monitorremovedv2",
+    "configreloaded",
+];
+
+#[derive(Clone, PartialEq, Eq)]
+enum Ipc {
+    Sway(String),
+    Hyprland(String),
+}
+
+// follows output changes of the compositor and asks the main loop to detect displays again
+pub struct HotplugWatcher {
+    ipc: Ipc,
+    stop: Arc<AtomicBool>,
+}
+
+impl HotplugWatcher {
+    pub fn start(config: &Config, notifier: Notifier) -> Option<Self> {
+        if !config.hotplug.unwrap_or(false) {
+            return None;
+        }
+        let Some(ipc) = ipc_for(config) else {
+            warn("Hotplug is enabled but neither sway nor Hyprland is running.");
+            return None;
+        };
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let watcher = HotplugWatcher {
+            ipc: ipc.clone(),
+            stop: stop.clone(),
+        };
+        let _ = thread::Builder::new()
+            .name("hotplug thread".to_string())
+            .spawn(move || follow(&ipc, &stop, &notifier));
+        Some(watcher)
+    }
+
+    // a watcher is restarted only when the config points it somewhere else
+    pub fn is_current(watcher: Option<&Self>, config: &Config) -> bool {
+        let wanted = config
+            .hotplug
+            .unwrap_or(false)
+            .then(|| ipc_for(config))
+            .flatten();
+        watcher.map(|watcher| &watcher.ipc) == wanted.as_ref()
+    }
+}
+
+impl Drop for HotplugWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn ipc_for(config: &Config) -> Option<Ipc> {
+    let kind = match config.display_provider {
+        Some(kind @ (ProviderKind::Sway | ProviderKind::Hyprland)) => kind,
+        _ => detect_kind()?,
+    };
+    match kind {
+        ProviderKind::Sway => config
+            .hotplug_socket
+            .clone()
+            .or_else(|| env::var("SWAYSOCK").ok())
+            .or_else(|| env::var("I3SOCK").ok())
+            .map(Ipc::Sway),
+        ProviderKind::Hyprland => config
+            .hotplug_socket
+            .clone()
+            .or_else(hyprland_socket)
+            .map(Ipc::Hyprland),
+        _ => None,
+    }
+}
+
+// newer releases keep their sockets in the runtime directory, older ones in /tmp
+fn hyprland_socket() -> Option<String> {
+    let signature = env::var("HYPRLAND_INSTANCE_SIGNATURE").ok()?;
+    let runtime = env::var("XDG_RUNTIME_DIR")
+        .map(|dir| format!("{dir}/hypr/{signature}/.socket2.sock"))
+        .ok();
+    runtime
+        .filter(|path| Path::new(path).exists())
+        .or_else(|| Some(format!("/tmp/hypr/{signature}/.socket2.sock")))
+}
+
+fn follow(ipc: &Ipc, stop: &AtomicBool, notifier: &Notifier) {
+    let mut reported = false;
+    while !stop.load(Ordering::Relaxed) {
+        let result = match ipc {
+            Ipc::Sway(path) => connect(path).and_then(|stream| {
+                subscribe_sway(&stream)?;
+                read_events(stream, stop, notifier, sway_output_changes)
+            }),
+            Ipc::Hyprland(path) => connect(path)
+                .and_then(|stream| read_events(stream, stop, notifier, hyprland_output_changes)),
+        };
+
+        match result {
+            Ok(()) => reported = false,
+            Err(e) if !reported => {
+                warn(&format!("Hotplug events are not available: {e}"));
+                reported = true;
+            }
+            Err(_) => {}
+        }
+
+        for _ in 0..RECONNECT_INTERVAL.as_secs() / POLL_INTERVAL.as_secs() {
+            if stop.load(Ordering::Relaxed) {
+                return;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+fn connect(path: &str) -> io::Result<UnixStream> {
+    let stream = UnixStream::connect(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("unable to connect to {path}: {e}")))?;
+    stream.set_read_timeout(Some(POLL_INTERVAL))?;
+    info(&format!("Following output changes on {path}."));
+    Ok(stream)
+}
+
+fn subscribe_sway(mut stream: &UnixStream) -> io::Result<()> {
+    let payload = br#"["output"]"#;
+    let mut message = SWAY_MAGIC.to_vec();
+    message.extend_from_slice(
+        &u32::try_from(payload.len())
+            .unwrap_or_default()
+            .to_ne_bytes(),
+    );
+    message.extend_from_slice(&SWAY_SUBSCRIBE.to_ne_bytes());
+    message.extend_from_slice(payload);
+    stream.write_all(&message)
+}
+
+// reads until the socket is closed, `changes` takes complete events off the buffer
+fn read_events(
+    mut stream: UnixStream,
+    stop: &AtomicBool,
+    notifier: &Notifier,
+    changes: fn(&mut Vec<u8>) -> io::Result<bool>,
+) -> io::Result<()> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    while !stop.load(Ordering::Relaxed) {
+        match stream.read(&mut chunk) {
+            Ok(0) => return Err(io::Error::other("the compositor closed the socket")),
+            Ok(read) => {
+                buffer.extend_from_slice(&chunk[..read]);
+                if changes(&mut buffer)? {
+                    notifier.send(MpscData::OutputsChanged);
+                }
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+// `i3-ipc`, payload length and message type in native byte order, then the payload
+fn sway_output_changes(buffer: &mut Vec<u8>) -> io::Result<bool> {
+    let mut changed = false;
+    while buffer.len() >= SWAY_HEADER {
+        if &buffer[..SWAY_MAGIC.len()] != SWAY_MAGIC {
+            return Err(io::Error::other("unexpected message from sway"));
+        }
+        let number = |at: usize| {
+            u32::from_ne_bytes([buffer[at], buffer[at + 1], buffer[at + 2], buffer[at + 3]])
+        };
+        let length = number(6) as usize;
+        let kind = number(10);
+        if length > SWAY_MAX_PAYLOAD {
+            return Err(io::Error::other(format!(
+                "sway sent a message of {length} bytes"
+            )));
+        }
+        if buffer.len() < SWAY_HEADER + length {
+            break;
+        }
+
+        if kind == SWAY_SUBSCRIBE {
+            let reply: Value = serde_json::from_slice(&buffer[SWAY_HEADER..SWAY_HEADER + length])
+                .unwrap_or_default();
+            if reply["success"] != Value::Bool(true) {
+                return Err(io::Error::other(format!(
+                    "sway refused the subscription: {reply}"
+                )));
+            }
+        }
+        changed |= kind == SWAY_OUTPUT_EVENT;
+        buffer.drain(..SWAY_HEADER + length);
+    }
+    Ok(changed)
+}
+
+// one `EVENT>>DATA` per line
+#[allow(clippy::unnecessary_wraps)]
+fn hyprland_output_changes(buffer: &mut Vec<u8>) -> io::Result<bool> {
+    let mut changed = false;
+    while let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+        let line: Vec<u8> = buffer.drain(..=end).collect();
+        let line = String::from_utf8_lossy(&line);
+        let event = line.split(">>").next().unwrap_or_default().trim();
+        changed |= HYPRLAND_EVENTS.contains(&event);
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::net::UnixListener;
+    use std::sync::mpsc;
+
+    use mio::{Poll, Token, Waker};
+
+    use super::*;
+    use crate::wallpaper::provider::query_displays;
+
+    const SWAY_EVENTS: &[u8] = include_bytes!("../../fixtures/sway-events.bin");
+    const HYPRLAND_EVENTS_FIXTURE: &str = include_str!("../../fixtures/hyprland-events.txt");
+
+    fn sway_message(kind: u32, payload: &str) -> Vec<u8> {
+        let mut message = SWAY_MAGIC.to_vec();
+        message.extend_from_slice(&u32::try_from(payload.len()).unwrap().to_ne_bytes());
+        message.extend_from_slice(&kind.to_ne_bytes());
+        message.extend_from_slice(payload.as_bytes());
+        message
+    }
+
+    fn hyprland_lines(events: &[&str]) -> Vec<u8> {
+        HYPRLAND_EVENTS_FIXTURE
+            .lines()
+            .filter(|line| events.contains(&line.split(">>").next().unwrap()))
+            .flat_map(|line| format!("{line}\n").into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn sway_output_events_are_changes() {
+        let mut buffer = SWAY_EVENTS.to_vec();
+        assert!(sway_output_changes(&mut buffer).unwrap());
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn sway_events_split_across_reads() {
+        // the subscribe reply and half of the first output event
+        let mut buffer = SWAY_EVENTS[..40].to_vec();
+        assert!(!sway_output_changes(&mut buffer).unwrap());
+        assert_eq!(buffer, SWAY_EVENTS[31..40]);
+
+        buffer.extend_from_slice(&SWAY_EVENTS[40..]);
+        assert!(sway_output_changes(&mut buffer).unwrap());
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn sway_ignores_other_events() {
+        let mut buffer = sway_message(SWAY_SUBSCRIBE, r#"{"success": true}"#);
+        buffer.extend(sway_message(0x8000_0000, r#"{"change": "focus"}"#));
+        buffer.extend(sway_message(0x8000_0003, r#"{"change": "new"}"#));
+        assert!(!sway_output_changes(&mut buffer).unwrap());
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn sway_rejects_refused_subscription_and_garbage() {
+        let mut buffer = sway_message(SWAY_SUBSCRIBE, r#"{"success": false}"#);
+        assert!(sway_output_changes(&mut buffer).is_err());
+
+        let mut buffer = b"not-ipc-message".to_vec();
+        assert!(sway_output_changes(&mut buffer).is_err());
+
+        // a header announcing a payload which is never buffered
+        let mut buffer = SWAY_MAGIC.to_vec();
+        buffer.extend(u32::MAX.to_ne_bytes());
+        buffer.extend(SWAY_OUTPUT_EVENT.to_ne_bytes());
+        assert!(sway_output_changes(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn hyprland_monitor_added_and_removed_are_changes() {
+        let mut buffer = hyprland_lines(&["monitoradded", "monitoraddedv2"]);
+        assert!(hyprland_output_changes(&mut buffer).unwrap());
+        assert!(buffer.is_empty());
+
+        let mut buffer = hyprland_lines(&["monitorremoved", "monitorremovedv2"]);
+        assert!(hyprland_output_changes(&mut buffer).unwrap());
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn hyprland_ignores_other_events() {
+        let mut buffer = hyprland_lines(&[
+            "activewindow",
+            "activewindowv2",
+            "workspace",
+            "workspacev2",
+            "focusedmon",
+        ]);
+        assert!(!buffer.is_empty());
+        assert!(!hyprland_output_changes(&mut buffer).unwrap());
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn hyprland_keeps_incomplete_lines() {
+        let mut buffer = b"focusedmon>>DP-1,1\nmonitorrem".to_vec();
+        assert!(!hyprland_output_changes(&mut buffer).unwrap());
+        assert_eq!(buffer, b"monitorrem");
+
+        buffer.extend_from_slice(b"oved>>HDMI-A-1\n");
+        assert!(hyprland_output_changes(&mut buffer).unwrap());
+    }
+
+    // replays a fixture on a socket in a temporary directory. every notification the hotplug
+    // thread sends to the main loop detects the displays again, the way the main loop does
+    // through `display_command`, and their names are returned
+    fn replay(name: &str, ipc: fn(String) -> Ipc, fixture: &'static [u8]) -> Vec<Vec<String>> {
+        let dir = env::temp_dir().join(format!("rpaper-hotplug-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ipc.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let ipc = ipc(path.to_string_lossy().into_owned());
+        let sway = matches!(ipc, Ipc::Sway(_));
+
+        let server = thread::spawn(move || {
+            let (mut client, _) = listener.accept().unwrap();
+            if sway {
+                let mut header = [0u8; SWAY_HEADER];
+                client.read_exact(&mut header).unwrap();
+                let length = u32::from_ne_bytes([header[6], header[7], header[8], header[9]]);
+                assert!(length as usize <= SWAY_MAX_PAYLOAD);
+                let mut payload = vec![0u8; length as usize];
+                client.read_exact(&mut payload).unwrap();
+                assert_eq!(payload, br#"["output"]"#);
+            }
+            // a few bytes at a time, so events arrive split across reads
+            for chunk in fixture.chunks(7) {
+                client.write_all(chunk).unwrap();
+                thread::sleep(Duration::from_millis(2));
+            }
+            client
+        });
+
+        let poll = Poll::new().unwrap();
+        let waker = Arc::new(Waker::new(poll.registry(), Token(0)).unwrap());
+        let (sender, receiver) = mpsc::channel();
+        let notifier = Notifier::new(sender, waker);
+        let stop = Arc::new(AtomicBool::new(false));
+        let follower = {
+            let stop = stop.clone();
+            thread::spawn(move || follow(&ipc, &stop, &notifier))
+        };
+
+        let (kind, outputs) = if sway {
+            (ProviderKind::Sway, "sway-outputs.json")
+        } else {
+            (ProviderKind::Hyprland, "hyprland-monitors.json")
+        };
+        let command = format!("cat {}/fixtures/{outputs}", env!("CARGO_MANIFEST_DIR"));
+
+        let client = server.join().unwrap();
+        let mut detected = Vec::new();
+        while let Ok(data) = receiver.recv_timeout(Duration::from_millis(500)) {
+            if matches!(data, MpscData::OutputsChanged) {
+                let displays = query_displays(kind, Some(&command)).unwrap();
+                detected.push(displays.into_iter().map(|display| display.name).collect());
+            }
+        }
+        stop.store(true, Ordering::Relaxed);
+        follower.join().unwrap();
+        drop(client);
+        let _ = fs::remove_dir_all(&dir);
+        detected
+    }
+
+    #[test]
+    fn sway_socket_replay_detects_displays_again() {
+        let detected = replay("sway", Ipc::Sway, SWAY_EVENTS);
+        assert!(!detected.is_empty());
+        assert!(detected.iter().all(|names| names == &["DP-1", "HDMI-A-1"]));
+    }
+
+    #[test]
+    fn hyprland_socket_replay_detects_displays_again() {
+        let detected = replay(
+            "hyprland",
+            Ipc::Hyprland,
+            HYPRLAND_EVENTS_FIXTURE.as_bytes(),
+        );
+        assert!(!detected.is_empty());
+        assert!(detected.iter().all(|names| names == &["DP-1", "HDMI-A-1"]));
+    }
+
+    #[test]
+    fn hyprland_socket_replay_ignores_other_events() {
+        let unrelated = hyprland_lines(&["activewindow", "workspace", "focusedmon"]);
+        assert!(replay("unrelated", Ipc::Hyprland, unrelated.leak()).is_empty());
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use crate::daemon::config::Config;
+use crate::daemon::daemon::MpscData;
+use crate::daemon::events::emit;
+use crate::daemon::notifier::Notifier;
+use crate::daemon::pool::{pool, Priority};
+use crate::daemon::request::process_request;
+use crate::logger::logger::{info, log, warn};
+use crate::unix_timestamp;
+use common::{Command, Event, JobProgress, JobStatus};
+
+const KEEP_FINISHED_JOBS: usize = 32;
+
+struct Job {
+    progress: JobProgress,
+    cancelled: Arc<AtomicBool>,
+}
+
+struct QueuedJob {
+    id: u64,
+    images: Vec<String>,
+    commands: Vec<Command>,
+    config: Config,
+}
+
+pub struct JobQueue {
+    jobs: Mutex<BTreeMap<u64, Job>>,
+    next_id: AtomicU64,
+    sender: Mutex<mpsc::Sender<QueuedJob>>,
+}
+
+impl JobQueue {
+    pub fn start(notifier: Notifier) -> Arc<Self> {
+        let (sender, receiver) = mpsc::channel::<QueuedJob>();
+
+        let queue = Arc::new(JobQueue {
+            jobs: Mutex::new(BTreeMap::new()),
+            next_id: AtomicU64::new(1),
+            sender: Mutex::new(sender),
+        });
+
+        let runner = queue.clone();
+        let _ = thread::Builder::new()
+            .name("job queue thread".to_string())
+            .spawn(move || {
+                for job in receiver {
+                    let id = job.id;
+                    runner.run(job);
+                    notifier.send(MpscData::JobFinished(id));
+                }
+            });
+
+        queue
+    }
+
+    pub fn push(
+        &self,
+        source: &str,
+        images: Vec<String>,
+        commands: Vec<Command>,
+        config: Config,
+    ) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        self.jobs.lock().unwrap().insert(
+            id,
+            Job {
+                progress: JobProgress {
+                    id,
+                    status: JobStatus::Queued,
+                    source: source.to_string(),
+                    done: 0,
+                    total: images.len(),
+                    current: None,
+                    errors: Vec::new(),
+                    queued_at: unix_timestamp(),
+                    finished_at: None,
+                },
+                cancelled: Arc::new(AtomicBool::new(false)),
+            },
+        );
+        self.prune();
+
+        let _ = self.sender.lock().unwrap().send(QueuedJob {
+            id,
+            images,
+            commands,
+            config,
+        });
+        info(&format!("Job {id} queued."));
+
+        id
+    }
+
+    pub fn get(&self, id: u64) -> Option<JobProgress> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(&id)
+            .map(|job| job.progress.clone())
+    }
+
+    pub fn list(&self) -> Vec<JobProgress> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .values()
+            .map(|job| job.progress.clone())
+            .collect()
+    }
+
+    pub fn cancel(&self, id: u64) -> Result<(), String> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs.get_mut(&id) else {
+            return Err(format!("job {id} does not exist"));
+        };
+
+        match job.progress.status {
+            JobStatus::Queued | JobStatus::Running => {
+                job.cancelled.store(true, Ordering::Relaxed);
+                info(&format!("Job {id} cancelled."));
+                Ok(())
+            }
+            JobStatus::Finished | JobStatus::Cancelled => {
+                Err(format!("job {id} is already finished"))
+            }
+        }
+    }
+
+    fn run(self: &Arc<Self>, job: QueuedJob) {
+        let Some(cancelled) = self.update(job.id, |progress| {
+            progress.status = JobStatus::Running;
+        }) else {
+            return;
+        };
+
+        log(&format!(
+            "Job {} started, {} images to process.",
+            job.id,
+            job.images.len()
+        ));
+
+        // background work, an interactive request never waits for a whole job
+        let commands = Arc::new(job.commands);
+        let config = Arc::new(job.config);
+        let tasks: Vec<Box<dyn FnOnce() + Send>> = job
+            .images
+            .into_iter()
+            .map(|image| {
+                let queue = self.clone();
+                let cancelled = cancelled.clone();
+                let commands = commands.clone();
+                let config = config.clone();
+                let id = job.id;
+                Box::new(move || {
+                    if cancelled.load(Ordering::Relaxed) {
+                        return;
+                    }
+
+                    queue.update(id, |progress| {
+                        progress.current = Some(image.clone());
+                    });
+
+                    let result = process_request(&commands, &config, &image);
+
+                    queue.update(id, |progress| {
+                        progress.done += 1;
+                        if let Err(e) = result {
+                            warn(&format!("Job {id}: {e}"));
+                            progress.errors.push(e);
+                        }
+                    });
+                }) as Box<dyn FnOnce() + Send>
+            })
+            .collect();
+
+        if let Err(e) = pool().run(Priority::Background, tasks) {
+            warn(&format!("Job {}: {e}", job.id));
+        }
+
+        let cancelled = cancelled.load(Ordering::Relaxed);
+        self.update(job.id, |progress| {
+            progress.current = None;
+            progress.finished_at = Some(unix_timestamp());
+            progress.status = if cancelled {
+                JobStatus::Cancelled
+            } else {
+                JobStatus::Finished
+            };
+        });
+    }
+
+    fn update(&self, id: u64, f: impl FnOnce(&mut JobProgress)) -> Option<Arc<AtomicBool>> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(&id)?;
+        f(&mut job.progress);
+        emit(&Event::JobProgress(job.progress.clone()));
+        Some(job.cancelled.clone())
+    }
+
+    fn prune(&self) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let finished: Vec<u64> = jobs
+            .values()
+            .filter(|job| job.progress.finished_at.is_some())
+            .map(|job| job.progress.id)
+            .collect();
+
+        if finished.len() > KEEP_FINISHED_JOBS {
+            for id in &finished[..finished.len() - KEEP_FINISHED_JOBS] {
+                jobs.remove(id);
+            }
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use std::sync::{Condvar, Mutex};
+
+pub const DEFAULT_HEAVY_JOBS: usize = 1;
+
+pub struct JobLimiter {
+    state: Mutex<LimiterState>,
+    condvar: Condvar,
+}
+
+struct LimiterState {
+    running: usize,
+    limit: usize,
+}
+
+pub struct JobPermit<'a> {
+    limiter: &'a JobLimiter,
+}
+
+impl JobLimiter {
+    pub fn new(limit: usize) -> Self {
+        JobLimiter {
+            state: Mutex::new(LimiterState {
+                running: 0,
+                limit: limit.max(1),
+            }),
+            condvar: Condvar::new(),
+        }
+    }
+
+    pub fn set_limit(&self, limit: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.limit = limit.max(1);
+        self.condvar.notify_all();
+    }
+
+    pub fn acquire(&self) -> JobPermit<'_> {
+        let mut state = self.state.lock().unwrap();
+        while state.running >= state.limit {
+            state = self.condvar.wait(state).unwrap();
+        }
+        state.running += 1;
+        JobPermit { limiter: self }
+    }
+}
+
+impl Drop for JobPermit<'_> {
+    fn drop(&mut self) {
+        let mut state = self.limiter.state.lock().unwrap();
+        state.running -= 1;
+        self.limiter.condvar.notify_one();
+    }
+}
@@ -1,6 +1,18 @@
 pub mod config;
+pub mod config_file;
 pub mod config_watcher;
+pub mod context;
 pub mod daemon;
 pub mod directory_watcher;
+pub mod displays;
+pub mod events;
+pub mod history;
+pub mod hotplug;
+pub mod jobs;
+pub mod limiter;
+pub mod notifier;
+pub mod pool;
 pub mod request;
+pub mod rotation;
 pub mod socket_listener;
+pub mod state;
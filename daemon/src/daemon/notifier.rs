@@ -0,0 +1,24 @@
+use std::sync::{mpsc, Arc};
+
+use mio::Waker;
+
+use crate::daemon::daemon::MpscData;
+
+#[derive(Clone)]
+pub struct Notifier {
+    sender: mpsc::Sender<MpscData>,
+    waker: Arc<Waker>,
+}
+
+impl Notifier {
+    pub fn new(sender: mpsc::Sender<MpscData>, waker: Arc<Waker>) -> Self {
+        Notifier { sender, waker }
+    }
+
+    // queues the message and wakes the main loop up to handle it
+    pub fn send(&self, data: MpscData) {
+        if self.sender.send(data).is_ok() {
+            let _ = self.waker.wake();
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Condvar, Mutex, OnceLock};
+use std::thread;
+
+use crate::logger::logger::err;
+
+pub const DEFAULT_WORKER_MEMORY: u64 = 1 << 30;
+
+static POOL: OnceLock<WorkerPool> = OnceLock::new();
+
+thread_local! {
+    // priority of the task running on this thread, inherited by the tasks it queues
+    static PRIORITY: Cell<Priority> = const { Cell::new(Priority::Interactive) };
+    // set on the threads of the pool
+    static WORKER: Cell<bool> = const { Cell::new(false) };
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    // requests somebody waits for
+    Interactive,
+    // caching of whole directories, never runs on the worker kept for requests
+    Background,
+}
+
+type Task = Box<dyn FnOnce() + Send>;
+
+struct QueuedTask {
+    batch: u64,
+    priority: Priority,
+    task: Task,
+}
+
+struct PoolState {
+    interactive: VecDeque<QueuedTask>,
+    background: VecDeque<QueuedTask>,
+    threads: usize,
+    workers: usize,
+    reserved_worker: bool,
+    memory_budget: u64,
+    memory_in_flight: u64,
+    memory_waiting: usize,
+}
+
+pub struct WorkerPool {
+    state: Mutex<PoolState>,
+    work: Condvar,
+    memory: Condvar,
+    next_batch: AtomicU64,
+}
+
+pub struct MemoryPermit {
+    bytes: u64,
+}
+
+// the pool shared by every part of the daemon, sized by `configure`
+pub fn pool() -> &'static WorkerPool {
+    POOL.get_or_init(|| WorkerPool {
+        state: Mutex::new(PoolState {
+            interactive: VecDeque::new(),
+            background: VecDeque::new(),
+            threads: 0,
+            workers: 0,
+            reserved_worker: false,
+            memory_budget: DEFAULT_WORKER_MEMORY,
+            memory_in_flight: 0,
+            memory_waiting: 0,
+        }),
+        work: Condvar::new(),
+        memory: Condvar::new(),
+        next_batch: AtomicU64::new(1),
+    })
+}
+
+pub fn current_priority() -> Priority {
+    PRIORITY.with(Cell::get)
+}
+
+pub fn default_workers() -> usize {
+    thread::available_parallelism().map_or(1, usize::from)
+}
+
+impl WorkerPool {
+    // workers are started right away, extra workers stop once they are idle. one more worker
+    // only takes interactive tasks, so requests never wait behind background work
+    pub fn configure(&'static self, threads: usize, memory_budget: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.threads = threads.max(1);
+        state.memory_budget = memory_budget;
+        if !state.reserved_worker {
+            state.reserved_worker = self.spawn_worker(true);
+        }
+        while state.workers < state.threads {
+            if !self.spawn_worker(false) {
+                break;
+            }
+            state.workers += 1;
+        }
+        self.work.notify_all();
+        self.memory.notify_all();
+    }
+
+    // runs the tasks on the pool and returns their results in order. a worker queueing tasks
+    // works on them as well, so it never waits for a free worker
+    pub fn run<T: Send + 'static>(
+        &self,
+        priority: Priority,
+        tasks: Vec<Box<dyn FnOnce() -> T + Send>>,
+    ) -> Result<Vec<T>, String> {
+        let total = tasks.len();
+        let batch = self.next_batch.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = mpsc::channel();
+        let inline = {
+            let mut state = self.state.lock().unwrap();
+            for (i, task) in tasks.into_iter().enumerate() {
+                let sender = sender.clone();
+                let task = QueuedTask {
+                    batch,
+                    priority,
+                    task: Box::new(move || {
+                        let _ = sender.send((i, task()));
+                    }),
+                };
+                match priority {
+                    Priority::Interactive => state.interactive.push_back(task),
+                    Priority::Background => state.background.push_back(task),
+                }
+            }
+            self.work.notify_all();
+            // without workers the caller does all of the work
+            WORKER.with(Cell::get) || (state.workers == 0 && !state.reserved_worker)
+        };
+        drop(sender);
+
+        if inline {
+            while let Some(task) = self.take_from_batch(batch) {
+                run_task(task);
+            }
+        }
+
+        let mut results: Vec<(usize, T)> = receiver.iter().collect();
+        if results.len() != total {
+            return Err("a worker task panicked".to_string());
+        }
+        results.sort_by_key(|(i, _)| *i);
+        Ok(results.into_iter().map(|(_, result)| result).collect())
+    }
+
+    // queues a task without waiting for it, for work started from threads which must not
+    // block, like the main loop
+    pub fn spawn(&self, priority: Priority, task: impl FnOnce() + Send + 'static) {
+        let task = QueuedTask {
+            batch: self.next_batch.fetch_add(1, Ordering::Relaxed),
+            priority,
+            task: Box::new(task),
+        };
+        let mut state = self.state.lock().unwrap();
+        // without workers the caller does the work
+        if state.workers == 0 && !state.reserved_worker {
+            drop(state);
+            run_task(task);
+            return;
+        }
+        match priority {
+            Priority::Interactive => state.interactive.push_back(task),
+            Priority::Background => state.background.push_back(task),
+        }
+        self.work.notify_all();
+    }
+
+    pub fn memory_budget(&self) -> u64 {
+        self.state.lock().unwrap().memory_budget
+    }
+
+    // waits until the estimated memory of an image fits in the budget. interactive requests
+    // go first, an image larger than the whole budget runs once nothing else is in flight
+    pub fn reserve(&self, bytes: u64, priority: Priority) -> MemoryPermit {
+        let mut state = self.state.lock().unwrap();
+        let interactive = priority == Priority::Interactive;
+        if interactive {
+            state.memory_waiting += 1;
+        }
+        while !(state.memory_in_flight == 0
+            || state.memory_in_flight + bytes <= state.memory_budget)
+            || (!interactive && state.memory_waiting > 0)
+        {
+            state = self.memory.wait(state).unwrap();
+        }
+        if interactive {
+            state.memory_waiting -= 1;
+        }
+        state.memory_in_flight += bytes;
+        MemoryPermit { bytes }
+    }
+
+    fn spawn_worker(&'static self, reserved: bool) -> bool {
+        let spawned = thread::Builder::new()
+            .name("worker thread".to_string())
+            .spawn(move || self.work(reserved));
+        if let Err(e) = &spawned {
+            err(&format!("Unable to start worker thread: {e}"));
+        }
+        spawned.is_ok()
+    }
+
+    fn work(&self, reserved: bool) {
+        WORKER.with(|worker| worker.set(true));
+        loop {
+            let task = {
+                let mut state = self.state.lock().unwrap();
+                loop {
+                    if !reserved && state.workers > state.threads {
+                        state.workers -= 1;
+                        return;
+                    }
+                    if let Some(task) = state.interactive.pop_front() {
+                        break task;
+                    }
+                    if !reserved {
+                        if let Some(task) = state.background.pop_front() {
+                            break task;
+                        }
+                    }
+                    state = self.work.wait(state).unwrap();
+                }
+            };
+            run_task(task);
+        }
+    }
+
+    fn take_from_batch(&self, batch: u64) -> Option<QueuedTask> {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        for queue in [&mut state.interactive, &mut state.background] {
+            if let Some(index) = queue.iter().position(|task| task.batch == batch) {
+                return queue.remove(index);
+            }
+        }
+        None
+    }
+}
+
+impl Drop for MemoryPermit {
+    fn drop(&mut self) {
+        let pool = pool();
+        pool.state.lock().unwrap().memory_in_flight -= self.bytes;
+        pool.memory.notify_all();
+    }
+}
+
+// a panicking task is logged, its batch returns an error instead of a result
+fn run_task(task: QueuedTask) {
+    let previous = PRIORITY.with(|priority| priority.replace(task.priority));
+    if panic::catch_unwind(AssertUnwindSafe(task.task)).is_err() {
+        err("A worker task panicked.");
+    }
+    PRIORITY.with(|priority| priority.set(previous));
+}
@@ -1,44 +1,90 @@
-use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
 use std::str::FromStr;
-use std::{fs, thread};
 
+use crate::cache::manifest;
 use crate::colorscheme::rwal::rwal_params::{OrderBy, RwalParams};
 use crate::colorscheme::scheme::{cache_scheme, get_cached_colors, set_scheme};
-use crate::logger::logger::log;
+use crate::logger::logger::{log, warn};
 use crate::wallpaper::display::{
-    cache_wallpaper, get_cached_image_names, get_cached_image_paths, set_wallpaper,
+    cache_display_wallpapers, cache_wallpaper, get_cached_image_names, get_cached_image_paths,
+    set_display_wallpapers, set_wallpaper,
 };
+use crate::wallpaper::backend::BackendKind;
+use crate::wallpaper::crop::Gravity;
+use crate::wallpaper::fit::{Background, FitMode};
 use crate::wallpaper::image::ImageOperations;
-use crate::{expand_user, unix_timestamp, WALLPAPERS_DIR};
-use common::Request;
+use crate::{expand_user, paths, unix_timestamp};
+use common::{Command, Display, LegacyRequest, Overrides, Request, PROTOCOL_VERSION};
 use rand::rng;
 use rand::seq::IndexedRandom;
 use serde_json::{json, Map, Value};
 
-use super::config::Config;
+use super::config::{Config, DisplayOptions};
+use super::config_file::validate_file;
+use super::context::Context;
+use super::events::{subscribe, Subscription};
+use super::history::{self, current_history};
+use super::pool::{current_priority, pool};
+use super::displays::{reapply_wallpaper, refresh_displays};
+use super::rotation::Step;
+use super::state::{self, current_state};
 
-#[derive(Clone)]
 pub struct RequestHandler {
     config: Config,
     message: String,
+    context: Context,
+    subscription: Option<Subscription>,
+    shutdown: bool,
 }
 
 impl RequestHandler {
-    pub fn new(config: Config, message: String) -> Self {
+    pub fn new(config: Config, message: String, context: Context) -> Self {
         if let Some(call_file) = &config.last_call_file {
             let _ = fs::write(call_file, &message);
         }
-        RequestHandler { config, message }
+        RequestHandler {
+            config,
+            message,
+            context,
+            subscription: None,
+            shutdown: false,
+        }
+    }
+
+    // the daemon is stopped only after the respond was sent back
+    pub fn shutdown_requested(&self) -> bool {
+        self.shutdown
+    }
+
+    pub fn take_subscription(&mut self) -> Option<Subscription> {
+        self.subscription.take()
     }
 
-    pub fn handle_image_request(&mut self, request: &Request, respond: &mut Value) -> Result<String, String> {
-        let image_path = expand_user(&request.image.clone().unwrap());
+    pub fn handle_image_request(
+        &mut self,
+        request: &Request,
+        respond: &mut Value,
+    ) -> Result<String, String> {
+        if request.independent || request.per_display.is_some() {
+            let config = collect_config_from_request(self.config.clone(), request);
+            let images = select_display_images(request, &config)?;
+            process_display_request(&request.commands, &config, &images)?;
+            record_history(&request.commands, &config, None, &images);
+            return Ok(format!("processed images for {} displays", images.len()));
+        }
+
+        let Some(image) = &request.image else {
+            return Err("command requires an image, pass it with -I".to_string());
+        };
+        let image_path = expand_user(image);
 
         if !Path::new(&image_path).exists() {
             return Err("path do not exists".to_string());
         }
 
-        if request.get_c_cache {
+        if request.commands.contains(&Command::GetColorCache) {
             add_key_to_value(
                 respond,
                 "c_cache",
@@ -52,264 +98,690 @@ impl RequestHandler {
                 } else {
                     Value::Null
                 },
-            )
+            );
         }
 
-        if request.get_w_cache {
+        if request.commands.contains(&Command::GetWallpaperCache) {
             add_key_to_value(
                 respond,
                 "w_cache",
                 if let Some(displays) = &self.config.displays {
-                    if let Some(img_ops) = &self.config.image_operations {
-                        get_cached_image_paths(
-                            &get_cached_image_names(&displays, &img_ops, &image_path),
-                            &expand_user(WALLPAPERS_DIR),
-                        )
-                        .iter()
-                        .map(|el| Value::String(el.clone()))
-                        .collect()
-                    } else {
-                        Value::Null
-                    }
+                    get_cached_image_paths(
+                        &get_cached_image_names(&self.config, displays, &image_path)?,
+                        &paths().wallpapers_dir(),
+                    )
+                    .iter()
+                    .map(|el| Value::String(el.clone()))
+                    .collect()
                 } else {
                     Value::Null
                 },
             );
         }
 
-        let config = collect_config_from_request(self.config.clone(), &request);
+        let config = collect_config_from_request(self.config.clone(), request);
 
         if is_dir(&image_path) {
             log(&format!("Collecting all wallpapers from {}.", &image_path));
-            let wallpapers = get_images_from_dir(&image_path);
-            let mut processed_images: u128 = 0;
+            let wallpapers = get_images_from_dir(&image_path)?;
             if request.affect_all {
                 log(&format!(
                     "Applying request for all images from {}.",
                     &image_path
                 ));
 
-                for chunk in wallpapers.chunks(4) {
-                    let mut handlers = Vec::new();
-
-                    for wallpaper in chunk {
-                        processed_images += 1;
-                        let wallpaper = wallpaper.clone();
-                        let config = config.clone();
-                        let request = request.clone();
-                        let thread = thread::spawn(move || {
-                            process_request(&request, &config, &wallpaper);
-                        });
-                        handlers.push(thread);
-                    }
-
-                    for handle in handlers {
-                        handle.join().unwrap();
-                    }
-                }
-            } else {
-                let wallpaper = select_random(wallpapers);
-                process_request(&request, &config, &wallpaper);
-                processed_images = 1;
+                let commands: Vec<Command> = request
+                    .commands
+                    .iter()
+                    .filter(|c| is_heavy(c))
+                    .copied()
+                    .collect();
+                let total = wallpapers.len();
+                let id = self
+                    .context
+                    .jobs
+                    .push(&image_path, wallpapers, commands, config);
+
+                add_key_to_value(respond, "job", json!(id));
+                return Ok(format!("job {id} queued for {total} images"));
             }
-            let end_time = unix_timestamp();
 
-            add_key_to_value(respond, "end_time", json!(end_time));
-            return Ok(format!("processed {} images", processed_images));
+            let wallpaper = select_random(&wallpapers)?;
+            process_request(&request.commands, &config, &wallpaper)?;
+            record_history(
+                &request.commands,
+                &config,
+                Some(&wallpaper),
+                &spanning_images(&config, &wallpaper),
+            );
+            return Ok("processed 1 images".to_string());
         }
 
         if !is_file_image(&image_path) {
             return Err("file is not an image or has unsuported format".to_string());
         }
 
-        process_request(&request, &config, &image_path);
+        process_request(&request.commands, &config, &image_path)?;
+        record_history(
+            &request.commands,
+            &config,
+            Some(&image_path),
+            &spanning_images(&config, &image_path),
+        );
         Ok("request processed".to_string())
     }
 
     pub fn handle(&mut self) -> String {
         let start_time = unix_timestamp();
 
-        let request_result = serde_json::from_str::<Request>(&self.message);
-
         let mut respond = Value::Object(Map::new());
+        add_key_to_value(&mut respond, "version", json!(PROTOCOL_VERSION));
         add_key_to_value(&mut respond, "start_time", json!(start_time));
 
-        if request_result.is_err() {
-            let end_time = unix_timestamp();
-            let time_elapsed = end_time - start_time;
+        match parse_request(&self.message) {
+            Ok(request) => self.handle_request(&request, &mut respond),
+            Err(msg) => add_key_to_value(&mut respond, "error", Value::String(msg)),
+        }
 
-            log(&format!("Request processed in {}ms.", time_elapsed,));
+        let end_time = unix_timestamp();
+        let time_elapsed = end_time - start_time;
 
-            add_key_to_value(&mut respond, "end_time", json!(end_time));
-            add_key_to_value(&mut respond, "time_elapsed", json!(time_elapsed));
+        log(&format!("Request processed in {time_elapsed}ms."));
 
+        add_key_to_value(&mut respond, "end_time", json!(end_time));
+        add_key_to_value(&mut respond, "time_elapsed", json!(time_elapsed));
+        if let Ok(msg) = serde_json::to_string(&respond) {
+            return msg;
+        }
+        "{error: \"caught unexpected error while serializing responce\"}".to_string()
+    }
+
+    fn handle_request(&mut self, request: &Request, respond: &mut Value) {
+        // handle requests which does not require image
+        if request.commands.contains(&Command::GetConfig) {
+            if let Ok(value) = serde_json::to_string(&self.config.clone()) {
+                add_key_to_value(respond, "config", Value::String(value));
+            }
+        }
+        for command in &request.commands {
+            match command {
+                Command::GetJob { id } => add_key_to_value(
+                    respond,
+                    "job",
+                    self.context
+                        .jobs
+                        .get(*id)
+                        .map_or(Value::Null, |job| json!(job)),
+                ),
+                Command::GetState => add_key_to_value(respond, "state", json!(current_state())),
+                Command::GetHistory => {
+                    add_key_to_value(respond, "history", json!(current_history()));
+                }
+                Command::PrevWallpaper | Command::NextWallpaper | Command::Undo => {
+                    match self.step_history(command) {
+                        Ok(msg) => add_key_to_value(respond, "message", Value::String(msg)),
+                        Err(msg) => add_key_to_value(respond, "error", Value::String(msg)),
+                    }
+                }
+                Command::GetDisplays => {
+                    add_key_to_value(respond, "displays", json!(self.config.displays));
+                    add_key_to_value(respond, "profile", json!(self.config.active_profile));
+                }
+                Command::SetProfile => match self.set_profile(request.profile.as_deref()) {
+                    Ok(msg) => add_key_to_value(respond, "message", Value::String(msg)),
+                    Err(msg) => add_key_to_value(respond, "error", Value::String(msg)),
+                },
+                Command::ValidateConfig => {
+                    let diagnostics = validate_file(&paths().config_path());
+                    add_key_to_value(
+                        respond,
+                        "validation",
+                        json!({
+                            "valid": diagnostics.is_valid(),
+                            "errors": diagnostics.errors,
+                            "warnings": diagnostics.warnings,
+                        }),
+                    );
+                }
+                Command::ListJobs => {
+                    add_key_to_value(respond, "jobs", json!(self.context.jobs.list()));
+                }
+                Command::CancelJob { id } => match self.context.jobs.cancel(*id) {
+                    Ok(()) => add_key_to_value(
+                        respond,
+                        "message",
+                        Value::String(format!("job {id} cancelled")),
+                    ),
+                    Err(msg) => add_key_to_value(respond, "error", Value::String(msg)),
+                },
+                Command::StartRotation
+                | Command::PauseRotation { .. }
+                | Command::ResumeRotation { .. }
+                | Command::StopRotation { .. }
+                | Command::RotateNext { .. }
+                | Command::RotatePrev { .. } => self.handle_rotation(command, request, respond),
+                Command::GetCacheStats | Command::PruneCache | Command::ClearCache => {
+                    self.handle_cache(command, request, respond);
+                }
+                Command::Subscribe => {
+                    self.subscription = Some(subscribe());
+                    add_key_to_value(respond, "message", json!("subscribed to events"));
+                }
+                Command::Shutdown => {
+                    self.shutdown = true;
+                    add_key_to_value(respond, "message", json!("daemon is shutting down"));
+                }
+                _ => {}
+            }
+        }
+        if request.commands.contains(&Command::GetCurrentScheme) {
             add_key_to_value(
-                &mut respond,
-                "error",
-                json!("error while deserializing request"),
+                respond,
+                "scheme",
+                if let Ok(colors) = fs::read_to_string(paths().colors_path()) {
+                    colors.lines().map(|c| Value::String(c.to_string())).collect()
+                } else {
+                    Value::Null
+                },
             );
-            if let Ok(msg) = serde_json::to_string(&respond) {
-                return msg;
+        }
+
+        // handle requests which does require image
+        if request.commands.iter().any(Command::requires_image) {
+            // heavy commands are limited, queries are answered right away
+            let limiter = self.context.limiter.clone();
+            let _permit = request
+                .commands
+                .iter()
+                .any(is_heavy)
+                .then(|| limiter.acquire());
+
+            match self.handle_image_request(request, respond) {
+                Ok(msg) => add_key_to_value(respond, "message", Value::String(msg)),
+                Err(msg) => add_key_to_value(respond, "error", Value::String(msg)),
             }
-            return "{error: \"caught unexpected error while serializing responce\"}"
-                .to_string();
         }
+    }
+}
 
-        let request = request_result.unwrap();
+impl RequestHandler {
+    // `None` returns to matching the connected displays, the choice survives restarts
+    fn set_profile(&mut self, name: Option<&str>) -> Result<String, String> {
+        if let Some(name) = name {
+            if !self
+                .config
+                .profiles
+                .as_ref()
+                .is_some_and(|profiles| profiles.contains_key(name))
+            {
+                return Err(format!("there is no profile named {name}"));
+            }
+        }
 
-        // handle requests which does not require image
-        if request.get_config {
-            if let Ok(value) = serde_json::to_string(&self.config.clone()) {
-                add_key_to_value(&mut respond, "config", Value::String(value));
+        state::update(|state| state.profile = name.map(ToString::to_string));
+        if refresh_displays(&self.context, self.context.config()) {
+            reapply_wallpaper(&self.context);
+        }
+        // later commands of the request use the new displays
+        self.config = self.context.config();
+
+        Ok(match &self.config.active_profile {
+            Some(profile) => format!("using display profile {profile}"),
+            None => "no display profile matches the connected displays".to_string(),
+        })
+    }
+
+    fn handle_rotation(&self, command: &Command, request: &Request, respond: &mut Value) {
+        match command {
+            Command::StartRotation => match self.start_rotation(request) {
+                Ok(id) => {
+                    add_key_to_value(respond, "rotation", json!(id));
+                    add_key_to_value(
+                        respond,
+                        "message",
+                        Value::String(format!("rotation {id} started")),
+                    );
+                }
+                Err(msg) => add_key_to_value(respond, "error", Value::String(msg)),
+            },
+            Command::PauseRotation { id } => {
+                let result = self.context.rotations.pause(*id);
+                respond_rotations(respond, result, "paused");
+            }
+            Command::ResumeRotation { id } => {
+                let result = self.context.rotations.resume(*id);
+                respond_rotations(respond, result, "resumed");
+            }
+            Command::StopRotation { id } => {
+                let result = self.context.rotations.stop(*id);
+                respond_rotations(respond, result, "stopped");
+            }
+            Command::RotateNext { id } | Command::RotatePrev { id } => {
+                let step = if matches!(command, Command::RotateNext { .. }) {
+                    Step::Next
+                } else {
+                    Step::Prev
+                };
+                match self.context.rotations.step(*id, step) {
+                    Ok(shown) => add_key_to_value(
+                        respond,
+                        "message",
+                        Value::String(
+                            shown
+                                .iter()
+                                .map(|(id, image)| format!("rotation {id} shows {image}"))
+                                .collect::<Vec<String>>()
+                                .join(", "),
+                        ),
+                    ),
+                    Err(msg) => add_key_to_value(respond, "error", Value::String(msg)),
+                }
             }
+            _ => {}
         }
-        if request.get_current_colorscheme {}
+    }
 
-        // handle requests which does require image
-        if request.image.is_some() {
-            match self.handle_image_request(&request, &mut respond) {
-                Ok(msg) => {
-                    add_key_to_value(&mut respond, "message", Value::String(msg));
-                },
-                Err(msg) => {
-                    add_key_to_value(&mut respond, "error", Value::String(msg));
-                },
+    fn handle_cache(&self, command: &Command, request: &Request, respond: &mut Value) {
+        match command {
+            Command::GetCacheStats => {
+                add_key_to_value(respond, "cache", json!(manifest::stats(&self.config)));
+            }
+            Command::PruneCache => {
+                let removed = manifest::prune();
+                add_key_to_value(
+                    respond,
+                    "message",
+                    Value::String(format!("removed {removed} of orphaned cache")),
+                );
             }
+            Command::ClearCache => match clear_cache(request) {
+                Ok(msg) => add_key_to_value(respond, "message", Value::String(msg)),
+                Err(msg) => add_key_to_value(respond, "error", Value::String(msg)),
+            },
+            _ => {}
         }
-        
-        let end_time = unix_timestamp();
-        let time_elapsed = end_time - start_time;
+    }
 
-        log(&format!("Request processed in {}ms.", time_elapsed,));
+    fn step_history(&self, command: &Command) -> Result<String, String> {
+        let _permit = self.context.limiter.acquire();
+        let entry = match command {
+            Command::PrevWallpaper => history::previous(&self.config)?,
+            Command::NextWallpaper => history::next(&self.config)?,
+            _ => history::undo(&self.config)?,
+        };
+        Ok(match entry.image {
+            Some(image) => format!("set {image} from the history"),
+            None => "set wallpapers of every display from the history".to_string(),
+        })
+    }
 
-        add_key_to_value(&mut respond, "end_time", json!(end_time));
-        add_key_to_value(&mut respond, "time_elapsed", json!(time_elapsed));
-        if let Ok(msg) = serde_json::to_string(&respond) {
-            return msg;
+    fn start_rotation(&self, request: &Request) -> Result<u64, String> {
+        let Some(rotation) = &request.rotation else {
+            return Err("no rotation passed with the request".to_string());
+        };
+        self.context
+            .rotations
+            .add(rotation.clone(), request.overrides.clone())
+    }
+}
+
+fn respond_rotations(respond: &mut Value, result: Result<Vec<u64>, String>, done: &str) {
+    match result {
+        Ok(ids) => {
+            let ids: Vec<String> = ids.iter().map(ToString::to_string).collect();
+            add_key_to_value(
+                respond,
+                "message",
+                Value::String(format!("rotation {} {done}", ids.join(", "))),
+            );
         }
-        return "{message: \"caught unexpected error while serializing responce\"}".to_string();
+        Err(msg) => add_key_to_value(respond, "error", Value::String(msg)),
+    }
+}
+
+// a directory clears the cache of every image in it
+fn clear_cache(request: &Request) -> Result<String, String> {
+    let images = match request.image.as_deref().map(expand_user) {
+        Some(path) if is_dir(&path) => get_images_from_dir(&path)?,
+        Some(path) => vec![path],
+        None => Vec::new(),
+    };
+    if images.is_empty() && request.cache_layout.is_none() {
+        return Err("pass an image with -I or a layout with --cache-layout".to_string());
+    }
+    let removed = manifest::clear(&images, request.cache_layout.as_deref());
+    Ok(format!("removed {removed} of cache"))
+}
+
+fn is_heavy(command: &Command) -> bool {
+    matches!(
+        command,
+        Command::SetWallpaper | Command::CacheWallpaper | Command::SetColors | Command::CacheColors
+    )
+}
+
+fn parse_request(message: &str) -> Result<Request, String> {
+    let Ok(value) = serde_json::from_str::<Value>(message) else {
+        return Err("error while deserializing request".to_string());
+    };
+
+    let Some(version) = value.get("version") else {
+        let legacy = serde_json::from_value::<LegacyRequest>(value)
+            .map_err(|e| format!("error while deserializing request: {e}"))?;
+        warn("Received request in legacy format, it will not be supported in the next release.");
+        return Ok(Request::from(legacy));
+    };
+
+    if version.as_u64() != Some(u64::from(PROTOCOL_VERSION)) {
+        return Err(format!(
+            "protocol version mismatch: client speaks version {version}, daemon speaks version {PROTOCOL_VERSION}. Update rpaper and rpaper-daemon to the same release"
+        ));
     }
+
+    serde_json::from_value::<Request>(value)
+        .map_err(|e| format!("error while deserializing request: {e}"))
 }
 
-fn collect_config_from_request(mut config: Config, request: &Request) -> Config {
-    if let Some(displays) = &request.displays {
-        config.displays = Some(displays.iter().map(|d| d.clone()).collect())
+pub fn collect_config_from_request(mut config: Config, request: &Request) -> Config {
+    let overrides = &request.overrides;
+
+    if let Some(displays) = &overrides.displays {
+        config.displays = Some(displays.clone());
     }
-    if let Some(templates) = &request.templates {
-        config.templates = Some(templates.iter().map(|t| t.clone()).collect())
+    if let Some(templates) = &overrides.templates {
+        config.templates = Some(templates.clone());
     }
-    if let Some(set_command) = &request.set_command {
-        config.set_command = Some(set_command.clone())
+    if let Some(set_command) = &overrides.set_command {
+        // an explicit command replaces any configured backend for this request
+        config.set_command = Some(set_command.clone());
+        config.backend = Some(BackendKind::Command);
+        config.display_options = None;
     }
-    if let Some(resize_alg) = &request.resize_alg {
-        config.resize_algorithm = Some(resize_alg.clone())
+    override_placement(&mut config, overrides);
+    if let Some(resize_alg) = &overrides.resize_alg {
+        config.resize_algorithm = Some(resize_alg.clone());
+    }
+    if let Some(rwal_params) = &mut config.rwal_params {
+        override_rwal_params(rwal_params, overrides);
+    }
+    if let Some(image_ops) = &mut config.image_operations {
+        override_image_operations(image_ops, overrides);
     }
 
-    let mut rwal_params: Option<RwalParams> = None;
-
-    if let Some(_rwal_params) = config.rwal_params {
-        let mut thumb_range = _rwal_params.thumb_range;
-        let mut clamp_range = _rwal_params.clamp_range;
-        let mut accent_color = _rwal_params.accent_color;
-        let colors = _rwal_params.colors;
-        let mut order = _rwal_params.order;
+    config
+}
 
-        if let Some(thumb) = &request.rwal_thumb {
-            if let Ok(value) = get_range_from_str::<u32>(&thumb) {
-                thumb_range = value;
+// like set_command, placement passed with the request applies to every display
+fn override_placement(config: &mut Config, overrides: &Overrides) {
+    if let Some(fit) = &overrides.fit {
+        match fit.parse::<FitMode>() {
+            Ok(fit) => {
+                config.fit = Some(fit);
+                clear_display_options(config, |options| options.fit = None);
             }
+            Err(e) => warn(&format!("Ignoring requested fit: {e}.")),
         }
-        if let Some(clamp) = &request.rwal_clamp {
-            if let Ok(value) = get_range_from_str::<f32>(&clamp) {
-                clamp_range = value;
+    }
+    if let Some(background) = &overrides.background {
+        match background.parse::<Background>() {
+            Ok(background) => {
+                config.background = Some(background);
+                clear_display_options(config, |options| options.background = None);
             }
+            Err(e) => warn(&format!("Ignoring requested background: {e}.")),
         }
-        if let Some(color) = request.rwal_accent {
-            accent_color = color
+    }
+    if let Some(gravity) = &overrides.gravity {
+        match gravity.parse::<Gravity>() {
+            Ok(gravity) => {
+                config.gravity = Some(gravity);
+                config.focal_point = None;
+                clear_display_options(config, |options| {
+                    options.gravity = None;
+                    options.focal_point = None;
+                });
+            }
+            Err(e) => warn(&format!("Ignoring requested gravity: {e}.")),
         }
-        if let Some(_order) = &request.rwal_order {
-            order = match OrderBy::from_str(&_order) {
-                Ok(value) => value,
-                Err(_) => OrderBy::Hue,
+    }
+    if let Some(focal_point) = &overrides.focal_point {
+        match get_range_from_str::<f32>(focal_point) {
+            Ok((x, y)) if (0.0..=1.0).contains(&x) && (0.0..=1.0).contains(&y) => {
+                config.focal_point = Some([x, y]);
+                clear_display_options(config, |options| {
+                    options.gravity = None;
+                    options.focal_point = None;
+                });
             }
+            _ => warn(&format!("Ignoring requested focal point {focal_point}.")),
         }
+    }
+}
 
-        rwal_params = Some(RwalParams::new(
-            thumb_range,
-            clamp_range,
-            accent_color,
-            colors,
-            order,
-        ));
+fn override_rwal_params(params: &mut RwalParams, overrides: &Overrides) {
+    if let Some(Ok(thumb_range)) = overrides.rwal_thumb.as_deref().map(get_range_from_str) {
+        params.thumb_range = thumb_range;
+    }
+    if let Some(Ok(clamp_range)) = overrides.rwal_clamp.as_deref().map(get_range_from_str) {
+        params.clamp_range = clamp_range;
+    }
+    if let Some(accent_color) = overrides.rwal_accent {
+        params.accent_color = accent_color;
+    }
+    if let Some(order) = &overrides.rwal_order {
+        params.order = OrderBy::from_str(order).unwrap_or(OrderBy::Hue);
+    }
+}
+
+fn override_image_operations(image_ops: &mut ImageOperations, overrides: &Overrides) {
+    image_ops.contrast = overrides.contrast.unwrap_or(image_ops.contrast);
+    image_ops.brightness = overrides.brightness.unwrap_or(image_ops.brightness);
+    image_ops.hue = overrides.hue.unwrap_or(image_ops.hue);
+    image_ops.blur = overrides.blur.unwrap_or(image_ops.blur);
+    image_ops.invert = overrides.invert.unwrap_or(image_ops.invert);
+    image_ops.flip_h = overrides.flip_h.unwrap_or(image_ops.flip_h);
+    image_ops.flip_v = overrides.flip_v.unwrap_or(image_ops.flip_v);
+}
+
+fn clear_display_options(config: &mut Config, clear: impl Fn(&mut DisplayOptions)) {
+    for options in config.display_options.iter_mut().flat_map(HashMap::values_mut) {
+        clear(options);
     }
+}
 
-    config.rwal_params = rwal_params;
+pub fn process_request(
+    commands: &[Command],
+    config: &Config,
+    image_path: &str,
+) -> Result<(), String> {
+    let (commands, owned_config, owned_path) =
+        (commands.to_vec(), config.clone(), image_path.to_string());
+    on_pool(config, &[image_path], move || {
+        process_image(&commands, &owned_config, &owned_path)
+    })
+}
 
-    let mut image_ops: Option<ImageOperations> = None;
+pub fn process_display_request(
+    commands: &[Command],
+    config: &Config,
+    images: &[(Display, String)],
+) -> Result<(), String> {
+    let image_paths: Vec<&str> = images.iter().map(|(_, path)| path.as_str()).collect();
+    let (commands, owned_config, owned_images) =
+        (commands.to_vec(), config.clone(), images.to_vec());
+    on_pool(config, &image_paths, move || {
+        process_display_images(&commands, &owned_config, &owned_images)
+    })
+}
 
-    if let Some(_image_ops) = config.image_operations {
-        let mut contrast = _image_ops.contrast;
-        let mut brightness = _image_ops.brightness;
-        let mut hue = _image_ops.hue;
-        let mut blur = _image_ops.blur;
-        let mut invert = _image_ops.invert;
-        let mut flip_h = _image_ops.flip_h;
-        let mut flip_v = _image_ops.flip_v;
+// requests are worked on by the pool like the images of jobs, so they count against its
+// workers and the memory estimated for `image_paths` against its budget
+pub fn on_pool(
+    config: &Config,
+    image_paths: &[&str],
+    task: impl FnOnce() -> Result<(), String> + Send + 'static,
+) -> Result<(), String> {
+    let bytes = estimate_memory(config, image_paths);
+    let task: Box<dyn FnOnce() -> Result<(), String> + Send> = Box::new(move || {
+        let _permit = pool().reserve(bytes, current_priority());
+        task()
+    });
+    pool()
+        .run(current_priority(), vec![task])?
+        .pop()
+        .unwrap_or(Ok(()))
+}
 
-        if let Some(_contrast) = request.contrast {
-            contrast = _contrast
-        }
-        if let Some(_brightness) = request.brightness {
-            brightness = _brightness
-        }
-        if let Some(_hue) = request.hue {
-            hue = _hue
-        }
-        if let Some(_blur) = request.blur {
-            blur = _blur
-        }
-        if let Some(_invert) = request.invert {
-            invert = _invert
-        }
-        if let Some(_flip_h) = request.flip_h {
-            flip_h = _flip_h
+fn process_image(commands: &[Command], config: &Config, image_path: &str) -> Result<(), String> {
+    log(&format!("Processing image {image_path}"));
+    if commands.contains(&Command::CacheColors) {
+        log(&format!("Caching colors for {image_path}"));
+        cache_scheme(config, image_path)?;
+    }
+    if commands.contains(&Command::CacheWallpaper) {
+        log(&format!("Caching wallpapers for {image_path}"));
+        cache_wallpaper(config, image_path)?;
+    }
+    if commands.contains(&Command::SetColors) {
+        log(&format!("Setting colors for {image_path}"));
+        set_scheme(config, image_path)?;
+    }
+    if commands.contains(&Command::SetWallpaper) {
+        log(&format!("Setting wallpapers for {image_path}"));
+        set_wallpaper(config, image_path)?;
+    }
+    Ok(())
+}
+
+fn process_display_images(
+    commands: &[Command],
+    config: &Config,
+    images: &[(Display, String)],
+) -> Result<(), String> {
+    // the palette is taken from the image of the first display
+    if let Some((_, image_path)) = images.first() {
+        if commands.contains(&Command::CacheColors) {
+            log(&format!("Caching colors for {image_path}"));
+            cache_scheme(config, image_path)?;
         }
-        if let Some(_flip_v) = request.flip_v {
-            flip_v = _flip_v
+    }
+    if commands.contains(&Command::CacheWallpaper) {
+        log("Caching wallpapers for every display");
+        cache_display_wallpapers(config, images)?;
+    }
+    if let Some((_, image_path)) = images.first() {
+        if commands.contains(&Command::SetColors) {
+            log(&format!("Setting colors for {image_path}"));
+            set_scheme(config, image_path)?;
         }
-
-        image_ops = Some(ImageOperations::new(
-            contrast, brightness, hue, blur, invert, flip_h, flip_v,
-        ));
     }
+    if commands.contains(&Command::SetWallpaper) {
+        log("Setting wallpapers for every display");
+        set_display_wallpapers(config, images)?;
+    }
+    Ok(())
+}
 
-    config.image_operations = image_ops;
+// decoded images and the canvases and crops rendered for the displays, the header of an
+// image is enough to know its size
+fn estimate_memory(config: &Config, image_paths: &[&str]) -> u64 {
+    let images: u64 = image_paths
+        .iter()
+        .filter_map(|path| image::image_dimensions(path).ok())
+        .map(|(width, height)| u64::from(width) * u64::from(height) * 4)
+        .sum();
+    let displays: u64 = config
+        .displays
+        .iter()
+        .flatten()
+        .map(|display| {
+            let (width, height) = display.physical_size();
+            u64::from(width) * u64::from(height) * 4 * 2
+        })
+        .sum();
+    images + displays
+}
 
+// requests of clients and rotation steps are kept, the images of jobs are only cached
+pub fn record_history(
+    commands: &[Command],
+    config: &Config,
+    image: Option<&str>,
+    images: &[(Display, String)],
+) {
+    let wallpaper = commands.contains(&Command::SetWallpaper);
+    let colors = commands.contains(&Command::SetColors);
+    if wallpaper || colors {
+        history::record(config, image, images, wallpaper, colors);
+    }
+}
+
+pub fn spanning_images(config: &Config, image_path: &str) -> Vec<(Display, String)> {
     config
+        .displays
+        .iter()
+        .flatten()
+        .map(|display| (display.clone(), image_path.to_string()))
+        .collect()
 }
 
-fn process_request(request: &Request, config: &Config, image_path: &str) {
-    log(&format!("Processing image {}", &image_path,));
-    if request.c_cache && !request.c_set {
-        log(&format!("Caching colors for {}", &image_path,));
-        cache_scheme(config, image_path);
-    }
-    if request.w_cache && !request.w_set {
-        log(&format!("Caching wallpapers for {}", &image_path,));
-        cache_wallpaper(config, image_path);
-    }
-    if request.c_set {
-        log(&format!("Setting colors for {}", &image_path,));
-        set_scheme(config, image_path);
+// displays without an entry in the mapping use the request image, directories are resolved
+// to a random image which is not shown on another display yet
+fn select_display_images(
+    request: &Request,
+    config: &Config,
+) -> Result<Vec<(Display, String)>, String> {
+    let Some(displays) = &config.displays else {
+        return Err("no displays are configured".to_string());
+    };
+
+    for name in request.per_display.iter().flatten().map(|(name, _)| name) {
+        if !displays.iter().any(|display| &display.name == name) {
+            warn(&format!("Display {name} is not configured, its image is ignored."));
+        }
     }
-    if request.w_set {
-        log(&format!("Setting wallpapers for {}", &image_path,));
-        set_wallpaper(config, image_path);
+
+    let mut images: Vec<(Display, String)> = Vec::new();
+    for display in displays {
+        let Some(source) = request
+            .per_display
+            .as_ref()
+            .and_then(|per_display| per_display.get(&display.name))
+            .or(request.image.as_ref())
+        else {
+            return Err(format!("no image for display {}", display.name));
+        };
+        let path = expand_user(source);
+
+        if !Path::new(&path).exists() {
+            return Err(format!("{path} do not exists"));
+        }
+
+        let image = if is_dir(&path) {
+            let wallpapers = get_images_from_dir(&path)?;
+            if wallpapers.is_empty() {
+                return Err(format!("{path} has no images"));
+            }
+            let unused: Vec<String> = wallpapers
+                .iter()
+                .filter(|wallpaper| !images.iter().any(|(_, image)| image == *wallpaper))
+                .cloned()
+                .collect();
+            select_random(if unused.is_empty() { &wallpapers } else { &unused })?
+        } else if is_file_image(&path) {
+            path
+        } else {
+            return Err(format!("{path} is not an image or has unsuported format"));
+        };
+
+        images.push((display.clone(), image));
     }
+
+    Ok(images)
 }
 
 fn get_range_from_str<T: std::str::FromStr>(s: &str) -> Result<(T, T), ()> {
-    let values = s.split("X").collect::<Vec<&str>>();
+    let values = s.split('X').collect::<Vec<&str>>();
     if values.len() != 2 {
         return Err(());
     }
@@ -320,24 +792,21 @@ fn get_range_from_str<T: std::str::FromStr>(s: &str) -> Result<(T, T), ()> {
     Ok((first, second))
 }
 
-fn is_dir(path: &str) -> bool {
-    fs::metadata(path)
-        .map(|meta| meta.is_dir())
-        .unwrap_or(false)
+pub fn is_dir(path: &str) -> bool {
+    fs::metadata(path).is_ok_and(|meta| meta.is_dir())
 }
 
-fn select_random(strings: Vec<String>) -> String {
+fn select_random(strings: &[String]) -> Result<String, String> {
     let mut rng = rng();
 
-    if let Some(random_string) = strings.choose(&mut rng) {
-        random_string.to_string()
-    } else {
-        panic!("Directory is empty")
-    }
+    strings
+        .choose(&mut rng)
+        .cloned()
+        .ok_or_else(|| "the directory has no images".to_string())
 }
 
-fn is_file_image(path: &str) -> bool {
-    if let Some(extension) = path.split(".").last() {
+pub fn is_file_image(path: &str) -> bool {
+    if let Some(extension) = path.rsplit('.').next() {
         return matches!(
             extension.to_lowercase().as_str(),
             "jpg" | "jpeg" | "webp" | "png" | "gif" | "bmp" | "tiff"
@@ -346,39 +815,32 @@ fn is_file_image(path: &str) -> bool {
     false
 }
 
-fn get_absolute_path(path: String) -> String {
-    let Ok(path) = PathBuf::from_str(&path);
-
-    return path
-        .canonicalize()
-        .unwrap_or_else(|_| path)
+fn get_absolute_path(path: &Path) -> String {
+    path.canonicalize()
+        .unwrap_or_else(|_| path.to_path_buf())
         .to_string_lossy()
-        .to_string();
+        .to_string()
 }
 
-fn get_images_from_dir(dir: &str) -> Vec<String> {
-    let path = Path::new(dir);
-    let files = fs::read_dir(path).unwrap();
+pub fn get_images_from_dir(dir: &str) -> Result<Vec<String>, String> {
+    let error = |e: std::io::Error| format!("unable to read {dir}: {e}");
+    let files = fs::read_dir(Path::new(dir)).map_err(error)?;
     let mut res: Vec<String> = Vec::new();
 
     for entry in files {
-        let entry = entry.unwrap();
-        let file_type = entry.file_type().unwrap();
+        let entry = entry.map_err(error)?;
+        let file_type = entry.file_type().map_err(error)?;
         if file_type.is_dir() {
-            res.extend(get_images_from_dir(&get_absolute_path(
-                entry.path().to_string_lossy().to_string(),
-            )))
+            res.extend(get_images_from_dir(&get_absolute_path(&entry.path()))?);
         } else if file_type.is_file() {
             if let Some(extension) = entry.path().extension() {
                 if is_file_image(extension.to_str().unwrap_or("")) {
-                    res.push(get_absolute_path(
-                        entry.path().to_string_lossy().to_string(),
-                    ))
+                    res.push(get_absolute_path(&entry.path()));
                 }
             }
         }
     }
-    res
+    Ok(res)
 }
 
 fn add_key_to_value(value: &mut Value, key: &str, new_value: Value) {
@@ -0,0 +1,375 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use rand::rng;
+use rand::seq::{IndexedRandom, SliceRandom};
+
+use crate::daemon::config::Config;
+use crate::daemon::limiter::JobLimiter;
+use crate::daemon::request::{
+    collect_config_from_request, get_images_from_dir, is_dir, is_file_image, process_request,
+    record_history, spanning_images,
+};
+use crate::daemon::state::{self, RotationState};
+use crate::logger::logger::{info, log, warn};
+use crate::{expand_user, unix_timestamp};
+use common::{Command, Overrides, Request, Rotation, RotationOrder, RotationSource};
+
+// images shown by a rotation which can be stepped back to
+const KEEP_PLAYED: usize = 50;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Next,
+    Prev,
+}
+
+struct RotationJob {
+    state: RotationState,
+    played: Vec<String>,
+    position: usize,
+    // images left in the current round of a shuffle
+    queue: Vec<String>,
+}
+
+pub struct Rotations {
+    jobs: Mutex<BTreeMap<u64, RotationJob>>,
+    next_id: AtomicU64,
+    changed: Condvar,
+    config: Arc<RwLock<Config>>,
+    limiter: Arc<JobLimiter>,
+}
+
+impl Rotations {
+    pub fn start(
+        config: Arc<RwLock<Config>>,
+        limiter: Arc<JobLimiter>,
+        restored: Vec<RotationState>,
+    ) -> Arc<Self> {
+        let next_id = restored.iter().map(|state| state.id).max().unwrap_or(0) + 1;
+        if !restored.is_empty() {
+            info(&format!("Restoring {} wallpaper rotations.", restored.len()));
+        }
+
+        let rotations = Arc::new(Rotations {
+            jobs: Mutex::new(
+                restored
+                    .into_iter()
+                    .map(|state| (state.id, RotationJob::new(state)))
+                    .collect(),
+            ),
+            next_id: AtomicU64::new(next_id),
+            changed: Condvar::new(),
+            config,
+            limiter,
+        });
+
+        let runner = rotations.clone();
+        let _ = thread::Builder::new()
+            .name("rotation thread".to_string())
+            .spawn(move || runner.run());
+
+        rotations
+    }
+
+    // the first wallpaper is set right away by the rotation thread
+    pub fn add(&self, rotation: Rotation, overrides: Overrides) -> Result<u64, String> {
+        if rotation.interval == 0 {
+            return Err("rotation interval has to be at least one second".to_string());
+        }
+        let total = collect_images(&rotation)?.len();
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let state = RotationState {
+            id,
+            rotation,
+            overrides,
+            paused: false,
+            current: None,
+            next_at: Some(unix_timestamp()),
+        };
+        self.update(|rotations| {
+            rotations.insert(id, RotationJob::new(state));
+        });
+        info(&format!("Rotation {id} started with {total} images."));
+
+        Ok(id)
+    }
+
+    pub fn pause(&self, id: Option<u64>) -> Result<Vec<u64>, String> {
+        self.modify(id, |job| {
+            job.state.paused = true;
+            job.state.next_at = None;
+        })
+    }
+
+    // the full interval passes before the next wallpaper
+    pub fn resume(&self, id: Option<u64>) -> Result<Vec<u64>, String> {
+        self.modify(id, |job| {
+            if job.state.paused {
+                job.state.paused = false;
+                job.state.next_at = Some(unix_timestamp() + job.interval());
+            }
+        })
+    }
+
+    pub fn stop(&self, id: Option<u64>) -> Result<Vec<u64>, String> {
+        let ids = self.select(id)?;
+        self.update(|rotations| {
+            for id in &ids {
+                rotations.remove(id);
+            }
+        });
+        Ok(ids)
+    }
+
+    // sets the next or previous image of the rotations, the interval starts over
+    pub fn step(&self, id: Option<u64>, step: Step) -> Result<Vec<(u64, String)>, String> {
+        let mut shown = Vec::new();
+        for id in self.select(id)? {
+            shown.push((id, self.show(id, step)?));
+        }
+        Ok(shown)
+    }
+
+    fn run(&self) {
+        let mut rotations = self.jobs.lock().unwrap();
+        loop {
+            let now = unix_timestamp();
+            let due = rotations
+                .values()
+                .find(|job| job.state.next_at.is_some_and(|at| at <= now))
+                .map(|job| job.state.id);
+
+            if let Some(id) = due {
+                drop(rotations);
+                if let Err(e) = self.show(id, Step::Next) {
+                    warn(&format!("Rotation {id}: {e}"));
+                    // a broken source is tried again after the interval instead of right away
+                    self.modify(Some(id), RotationJob::reschedule).ok();
+                }
+                rotations = self.jobs.lock().unwrap();
+                continue;
+            }
+
+            let wait = rotations
+                .values()
+                .filter_map(|job| job.state.next_at)
+                .min()
+                .map(|at| Duration::from_millis(u64::try_from(at - now).unwrap_or(u64::MAX)));
+            rotations = match wait {
+                Some(wait) => self.changed.wait_timeout(rotations, wait).unwrap().0,
+                None => self.changed.wait(rotations).unwrap(),
+            };
+        }
+    }
+
+    fn show(&self, id: u64, step: Step) -> Result<String, String> {
+        let Some((rotation, overrides)) = self.jobs.lock().unwrap().get(&id).map(|job| {
+            (job.state.rotation.clone(), job.state.overrides.clone())
+        }) else {
+            return Err(format!("rotation {id} does not exist"));
+        };
+
+        // the source is read again every time, so added and removed images are picked up
+        let images = collect_images(&rotation)?;
+        let image = self.update(|rotations| {
+            let job = rotations
+                .get_mut(&id)
+                .ok_or_else(|| format!("rotation {id} does not exist"))?;
+            let image = job.pick(&images, step)?;
+            job.state.current = Some(image.clone());
+            job.reschedule();
+            Ok::<_, String>(image)
+        })?;
+
+        log(&format!("Rotation {id} shows {image}."));
+        let mut commands = vec![Command::SetWallpaper];
+        if rotation.colors {
+            commands.push(Command::SetColors);
+        }
+        let mut request = Request::new(commands.clone());
+        request.overrides = overrides;
+        let config = collect_config_from_request(self.config.read().unwrap().clone(), &request);
+
+        let _permit = self.limiter.acquire();
+        process_request(&commands, &config, &image)?;
+        record_history(
+            &commands,
+            &config,
+            Some(&image),
+            &spanning_images(&config, &image),
+        );
+        Ok(image)
+    }
+
+    fn select(&self, id: Option<u64>) -> Result<Vec<u64>, String> {
+        let rotations = self.jobs.lock().unwrap();
+        match id {
+            Some(id) if rotations.contains_key(&id) => Ok(vec![id]),
+            Some(id) => Err(format!("rotation {id} does not exist")),
+            None if rotations.is_empty() => Err("no rotation is running".to_string()),
+            None => Ok(rotations.keys().copied().collect()),
+        }
+    }
+
+    fn modify(&self, id: Option<u64>, f: impl Fn(&mut RotationJob)) -> Result<Vec<u64>, String> {
+        let ids = self.select(id)?;
+        self.update(|rotations| {
+            for id in &ids {
+                if let Some(job) = rotations.get_mut(id) {
+                    f(job);
+                }
+            }
+        });
+        Ok(ids)
+    }
+
+    // changes are saved to the daemon state and wake the rotation thread up
+    fn update<T>(&self, f: impl FnOnce(&mut BTreeMap<u64, RotationJob>) -> T) -> T {
+        let mut rotations = self.jobs.lock().unwrap();
+        let result = f(&mut rotations);
+        let states: Vec<RotationState> = rotations.values().map(|job| job.state.clone()).collect();
+        state::update(|state| state.rotations = states);
+        self.changed.notify_all();
+        result
+    }
+}
+
+impl RotationJob {
+    fn new(state: RotationState) -> Self {
+        RotationJob {
+            state,
+            played: Vec::new(),
+            position: 0,
+            queue: Vec::new(),
+        }
+    }
+
+    fn interval(&self) -> u128 {
+        u128::from(self.state.rotation.interval) * 1000
+    }
+
+    fn reschedule(&mut self) {
+        if !self.state.paused {
+            self.state.next_at = Some(unix_timestamp() + self.interval());
+        }
+    }
+
+    // steps through the images shown before, then picks a new one in the order of the rotation
+    fn pick(&mut self, images: &[String], step: Step) -> Result<String, String> {
+        match step {
+            Step::Next if self.position + 1 < self.played.len() => {
+                self.position += 1;
+                return Ok(self.played[self.position].clone());
+            }
+            Step::Prev if self.position > 0 => {
+                self.position -= 1;
+                return Ok(self.played[self.position].clone());
+            }
+            _ => {}
+        }
+
+        let current = self.state.current.as_deref();
+        let image = match (self.state.rotation.order, step) {
+            (RotationOrder::Name | RotationOrder::Mtime, _) => {
+                let index = current.and_then(|current| images.iter().position(|i| i == current));
+                let index = match (index, step) {
+                    (Some(index), Step::Next) => (index + 1) % images.len(),
+                    (Some(index), Step::Prev) => (index + images.len() - 1) % images.len(),
+                    (None, Step::Next) => 0,
+                    (None, Step::Prev) => images.len() - 1,
+                };
+                images[index].clone()
+            }
+            (_, Step::Prev) => return Err("there is no previous image in this rotation".to_string()),
+            (RotationOrder::Random, Step::Next) => {
+                let others: Vec<&String> = images
+                    .iter()
+                    .filter(|image| Some(image.as_str()) != current)
+                    .collect();
+                let image = others.choose(&mut rng()).copied();
+                image.unwrap_or(&images[0]).clone()
+            }
+            (RotationOrder::Shuffle, Step::Next) => {
+                let available: HashSet<&String> = images.iter().collect();
+                self.queue.retain(|image| available.contains(image));
+                if self.queue.is_empty() {
+                    self.queue = images.to_vec();
+                    self.queue.shuffle(&mut rng());
+                    // a new round does not start with the image which ended the last one
+                    let last = self.queue.len() - 1;
+                    if self.queue.len() > 1 && Some(self.queue[last].as_str()) == current {
+                        self.queue.swap(0, last);
+                    }
+                }
+                self.queue.pop().unwrap_or_default()
+            }
+        };
+
+        if step == Step::Prev {
+            self.played.insert(0, image.clone());
+            self.played.truncate(KEEP_PLAYED);
+            self.position = 0;
+        } else {
+            self.played.push(image.clone());
+            if self.played.len() > KEEP_PLAYED {
+                self.played.remove(0);
+            }
+            self.position = self.played.len() - 1;
+        }
+        Ok(image)
+    }
+}
+
+// images of the source in the order of the rotation, random orders get them sorted by name
+fn collect_images(rotation: &Rotation) -> Result<Vec<String>, String> {
+    let mut images = match &rotation.source {
+        RotationSource::Directory(dir) => {
+            let dir = expand_user(dir);
+            if !is_dir(&dir) {
+                return Err(format!("{dir} is not a directory"));
+            }
+            get_images_from_dir(&dir)?
+        }
+        RotationSource::List(paths) => paths.iter().map(|path| expand_user(path)).collect(),
+        RotationSource::Playlist(playlist) => read_playlist(&expand_user(playlist))?,
+    };
+    images.retain(|image| is_file_image(image) && Path::new(image).is_file());
+    if images.is_empty() {
+        return Err("the rotation source has no images".to_string());
+    }
+
+    if rotation.order == RotationOrder::Mtime {
+        images.sort_by_cached_key(|image| {
+            fs::metadata(image)
+                .and_then(|meta| meta.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH)
+        });
+    } else {
+        images.sort();
+    }
+    Ok(images)
+}
+
+// relative paths are relative to the playlist
+fn read_playlist(playlist: &str) -> Result<Vec<String>, String> {
+    let data =
+        fs::read_to_string(playlist).map_err(|e| format!("unable to read {playlist}: {e}"))?;
+    let base = Path::new(playlist).parent().unwrap_or(Path::new("."));
+    Ok(data
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            base.join(expand_user(line))
+                .to_string_lossy()
+                .to_string()
+        })
+        .collect())
+}
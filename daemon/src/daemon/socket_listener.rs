@@ -1,47 +1,152 @@
-use std::io::{BufRead, BufReader, Write};
-use std::os::unix::net::UnixListener;
-use std::sync::mpsc::{self, Receiver, Sender};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::fd::AsFd;
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
 use std::thread;
+use std::time::Duration;
 
-use crate::logger::logger::{err, info};
-
-use super::daemon::MpscData;
-
-pub fn start_socket_listener(
-    socket_path: &str,
-    sender: mpsc::Sender<MpscData>,
-) -> mpsc::Sender<MpscData> {
-    info(&format!("Monitoring socket file at {}.", socket_path));
-    let (listener_sender, listener_receiver): (Sender<MpscData>, Receiver<MpscData>) =
-        mpsc::channel();
-    let listener =
-        UnixListener::bind(socket_path).unwrap_or_else(|_| panic!("Unable to create socket"));
-
-    thread::spawn(move || {
-        for stream in listener.incoming() {
-            match stream {
-                Ok(mut stream) => {
-                    let mut reader = BufReader::new(&stream);
-                    let mut buffer = String::new();
-
-                    if let Err(_) = reader.read_line(&mut buffer) {
-                        continue;
-                    }
-
-                    let request = buffer.trim().to_string();
-                    let _ = sender.send(MpscData::SocketRequest(request));
-
-                    if let Ok(MpscData::Respond(mut handler)) = listener_receiver.recv() {
-                        let respond = handler.handle().replace("\\\"", "\"");
-                        let _ = stream.write_all(respond.as_bytes());
-                    }
+use common::PROTOCOL_VERSION;
+use mio::net::UnixListener;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use serde_json::json;
+
+use crate::daemon::context::Context;
+use crate::daemon::daemon::MpscData;
+use crate::daemon::events::Subscription;
+use crate::daemon::request::RequestHandler;
+use crate::logger::logger::{err, info, log, warn};
+
+// clients served at once, subscribers included
+const MAX_CLIENTS: usize = 64;
+// how often a subscriber without events is checked for a hangup
+const HANGUP_INTERVAL: Duration = Duration::from_secs(1);
+
+// a client which does not send its request in time gives its slot back
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+static CLIENTS: AtomicUsize = AtomicUsize::new(0);
+
+struct ClientSlot;
+
+pub fn bind_socket(socket_path: &str) -> UnixListener {
+    info(&format!("Monitoring socket file at {socket_path}."));
+    UnixListener::bind(socket_path).unwrap_or_else(|_| panic!("Unable to create socket"))
+}
+
+pub fn accept_clients(listener: &UnixListener, context: &Context) {
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let stream = UnixStream::from(stream);
+                let Some(slot) = ClientSlot::take() else {
+                    warn("Too many clients, the connection is refused.");
+                    refuse_client(stream);
+                    continue;
+                };
+                // clients are served with blocking io on their own thread
+                if let Err(e) = stream
+                    .set_nonblocking(false)
+                    .and_then(|()| stream.set_read_timeout(Some(REQUEST_TIMEOUT)))
+                {
+                    err(&format!("Error: {e}"));
+                    continue;
+                }
+                let context = context.clone();
+                let _ = thread::Builder::new()
+                    .name("socket client thread".to_string())
+                    .spawn(move || {
+                        handle_client(stream, &context);
+                        drop(slot);
+                    });
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+            Err(e) => {
+                err(&format!("Error: {e}"));
+                break;
+            }
+        }
+    }
+}
+
+fn handle_client(mut stream: UnixStream, context: &Context) {
+    let mut reader = BufReader::new(&stream);
+    let mut buffer = String::new();
+
+    if reader.read_line(&mut buffer).is_err() {
+        return;
+    }
+
+    log("Received socket request.");
+    let mut handler =
+        RequestHandler::new(context.config(), buffer.trim().to_string(), context.clone());
+    let respond = handler.handle();
+    let written = stream.write_all(format!("{respond}\n").as_bytes());
+
+    if handler.shutdown_requested() {
+        context.notifier.send(MpscData::Shutdown);
+    }
+    if written.is_err() {
+        return;
+    }
+
+    // subscribers keep the connection open and receive one event per line
+    if let Some(subscription) = handler.take_subscription() {
+        send_events(&mut stream, &subscription);
+    }
+}
+
+// stops once the client hangs up, even when no events are emitted meanwhile
+fn send_events(stream: &mut UnixStream, subscription: &Subscription) {
+    loop {
+        match subscription.events.recv_timeout(HANGUP_INTERVAL) {
+            Ok(event) => {
+                if stream.write_all(format!("{event}\n").as_bytes()).is_err() {
+                    return;
                 }
-                Err(e) => {
-                    err(&format!("Error: {}", e));
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if hung_up(stream) {
+                    return;
                 }
             }
+            Err(RecvTimeoutError::Disconnected) => return,
         }
+    }
+}
+
+// a subscriber sends nothing after its request, so a readable stream is at its end
+fn hung_up(stream: &mut UnixStream) -> bool {
+    let mut fds = [PollFd::new(stream.as_fd(), PollFlags::POLLIN)];
+    match poll(&mut fds, PollTimeout::ZERO) {
+        Ok(0) => false,
+        Ok(_) => matches!(stream.read(&mut [0; 64]), Ok(0) | Err(_)),
+        Err(_) => true,
+    }
+}
+
+// answered from the main loop, the stream is still non-blocking and the request is not read
+fn refuse_client(mut stream: UnixStream) {
+    let respond = json!({
+        "version": PROTOCOL_VERSION,
+        "error": "too many clients are connected to the daemon, try again later",
     });
+    let _ = stream.write_all(format!("{respond}\n").as_bytes());
+}
+
+impl ClientSlot {
+    fn take() -> Option<Self> {
+        CLIENTS
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |clients| {
+                (clients < MAX_CLIENTS).then_some(clients + 1)
+            })
+            .ok()
+            .map(|_| ClientSlot)
+    }
+}
 
-    listener_sender
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        CLIENTS.fetch_sub(1, Ordering::AcqRel);
+    }
 }
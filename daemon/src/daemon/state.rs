@@ -0,0 +1,212 @@
+use std::fs;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cache::store::write_atomic;
+use crate::colorscheme::rwal::rwal_params::RwalParams;
+use crate::colorscheme::scheme::set_scheme;
+use crate::daemon::config::Config;
+use crate::daemon::request::on_pool;
+use crate::logger::logger::{info, warn};
+use crate::wallpaper::display::{set_display_wallpapers, set_wallpaper};
+use crate::wallpaper::image::ImageOperations;
+use crate::{paths, unix_timestamp};
+use common::{Display, Overrides, Rotation};
+
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    pub image: Option<String>,
+    pub displays: Vec<DisplayState>,
+    pub palette: Vec<String>,
+    pub palette_image: Option<String>,
+    pub image_operations: Option<ImageOperations>,
+    pub rwal_params: Option<RwalParams>,
+    pub templates: Vec<TemplateState>,
+    // display profile selected by request, kept until another one is selected
+    pub profile: Option<String>,
+    // rotations are started again with the daemon
+    pub rotations: Vec<RotationState>,
+    // process groups of long running wallpaper setters
+    pub setters: Vec<SetterState>,
+    pub updated_at: u128,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DisplayState {
+    pub display: Display,
+    pub image: String,
+    pub path: String,
+    pub set_at: u128,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RotationState {
+    pub id: u64,
+    pub rotation: Rotation,
+    pub overrides: Overrides,
+    pub paused: bool,
+    pub current: Option<String>,
+    // none while paused
+    pub next_at: Option<u128>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SetterState {
+    pub display: String,
+    pub pid: i32,
+    pub started_at: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TemplateState {
+    pub template: String,
+    pub path: String,
+    pub applied_at: u128,
+}
+
+impl State {
+    pub fn set_display(&mut self, display: &Display, image: &str, path: &str) {
+        let display_state = DisplayState {
+            display: display.clone(),
+            image: image.to_string(),
+            path: path.to_string(),
+            set_at: unix_timestamp(),
+        };
+
+        match self
+            .displays
+            .iter_mut()
+            .find(|d| d.display.name == display.name)
+        {
+            Some(current) => *current = display_state,
+            None => self.displays.push(display_state),
+        }
+    }
+
+    pub fn set_template(&mut self, template: &str, path: &str) {
+        let template_state = TemplateState {
+            template: template.to_string(),
+            path: path.to_string(),
+            applied_at: unix_timestamp(),
+        };
+
+        match self.templates.iter_mut().find(|t| t.template == template) {
+            Some(current) => *current = template_state,
+            None => self.templates.push(template_state),
+        }
+    }
+}
+
+pub fn current_state() -> State {
+    update_state(|_| {}, false)
+}
+
+pub fn update(f: impl FnOnce(&mut State)) {
+    update_state(f, true);
+}
+
+pub fn restore(config: &Config) -> Result<(), String> {
+    reapply_wallpaper(config)?;
+
+    let state = current_state();
+    if let Some(image) = &state.palette_image {
+        info(&format!("Restoring colorscheme of {image}."));
+        set_scheme(&restored_config(config, &state), image)?;
+    }
+
+    Ok(())
+}
+
+// sets the last wallpaper again on the displays of the config, used when their layout changes
+pub fn reapply_wallpaper(config: &Config) -> Result<(), String> {
+    let state = current_state();
+    let image_paths: Vec<&str> = state
+        .image
+        .iter()
+        .chain(state.displays.iter().map(|saved| &saved.image))
+        .map(String::as_str)
+        .collect();
+    let restored = restored_config(config, &state);
+    let owned_state = state.clone();
+    on_pool(config, &image_paths, move || {
+        reapply_state(&restored, &owned_state)
+    })
+}
+
+fn reapply_state(config: &Config, state: &State) -> Result<(), String> {
+    if let Some(image) = &state.image {
+        info(&format!("Restoring wallpaper {image}."));
+        set_wallpaper(config, image)?;
+    } else if !state.displays.is_empty() {
+        info("Restoring wallpapers of every display.");
+        let saved: Vec<(Display, String)> = state
+            .displays
+            .iter()
+            .map(|saved| (saved.display.clone(), saved.image.clone()))
+            .collect();
+        let images = display_images(config, &saved);
+        set_display_wallpapers(config, &images)?;
+    }
+
+    Ok(())
+}
+
+// the geometry is taken from the config, the display may have been moved since,
+// displays without a saved image get the image of the first one
+pub fn display_images(config: &Config, saved: &[(Display, String)]) -> Vec<(Display, String)> {
+    let Some(displays) = &config.displays else {
+        return saved.to_vec();
+    };
+    displays
+        .iter()
+        .filter_map(|display| {
+            let (_, image) = saved
+                .iter()
+                .find(|(saved, _)| saved.name == display.name)
+                .or(saved.first())?;
+            Some((display.clone(), image.clone()))
+        })
+        .collect()
+}
+
+fn restored_config(config: &Config, state: &State) -> Config {
+    let mut config = config.clone();
+    if state.image_operations.is_some() {
+        config.image_operations.clone_from(&state.image_operations);
+    }
+    if state.rwal_params.is_some() {
+        config.rwal_params.clone_from(&state.rwal_params);
+    }
+    config
+}
+
+fn update_state(f: impl FnOnce(&mut State), save: bool) -> State {
+    let mut guard = STATE.lock().unwrap();
+    let state = guard.get_or_insert_with(load);
+
+    f(state);
+
+    if save {
+        state.updated_at = unix_timestamp();
+        if let Ok(data) = serde_json::to_string_pretty(state) {
+            if let Err(e) = write_atomic(&paths().state_path(), |temp_path| {
+                fs::write(temp_path, data).map_err(|e| e.to_string())
+            }) {
+                warn(&format!("Unable to save daemon state: {e}"));
+            }
+        }
+    }
+
+    state.clone()
+}
+
+fn load() -> State {
+    fs::read_to_string(paths().state_path())
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
@@ -1,3 +1,4 @@
+mod cache;
 mod colorscheme;
 mod daemon;
 mod logger;
@@ -5,27 +6,34 @@ mod template;
 mod wallpaper;
 
 use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
 use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::sync::OnceLock;
+use std::os::unix::process::CommandExt;
 use std::process::Command;
 use std::thread;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use common::paths::{ConfigFormat, PathKeys};
+use common::{PathOverrides, Paths, Request};
+use nix::sys::signal::{pthread_sigmask, SigSet, SigmaskHow};
 use nix::unistd::fork;
 use sha2::{Digest, Sha256};
 
+use crate::daemon::config::Config;
 use crate::daemon::daemon::Daemon;
 
-const SOCKET_PATH: &str = "/tmp/rpaper-daemon";
-const CONFIG_PATH: &str = "~/.config/rpaper/config.json";
-const CONFIG_DIR: &str = "~/.config/rpaper";
-const CACHE_DIR: &str = "~/.cache/rpaper";
-const COLORS_DIR: &str = "~/.cache/rpaper/rwal";
-const COLORS_PATH: &str = "~/.cache/rpaper/rwal/colors";
-const WALLPAPERS_DIR: &str = "~/.cache/rpaper/wallpapers";
+static PATHS: OnceLock<Paths> = OnceLock::new();
 
 //const DAEMON_NAME: &str = "rpaper-daemon";
 
+pub fn paths() -> &'static Paths {
+    PATHS.get().expect("paths are resolved on startup")
+}
+
 pub fn unix_timestamp() -> u128 {
     let start = SystemTime::now();
 
@@ -74,32 +82,33 @@ pub fn encode_string(string: &str) -> String {
     hex::encode(hasher.finalize())
 }
 
-pub fn system(command: &str) {
-    let mut child = Command::new("nohup")
-        .args(["bash", "-c", &command])
-        .stdout(std::process::Stdio::null())
-        .stderr(std::process::Stdio::null())
-        .spawn()
-        .expect("");
-    let _exit_status = child.wait().expect("Failed to wait for command");
+// the daemon blocks termination signals for its signalfd and children would inherit the mask
+pub fn unblock_signals(command: &mut Command) -> &mut Command {
+    unsafe {
+        command.pre_exec(|| {
+            pthread_sigmask(SigmaskHow::SIG_SETMASK, Some(&SigSet::empty()), None)
+                .map_err(std::io::Error::from)
+        })
+    }
 }
 
-pub fn spawn(command: &str) {
-    Command::new("nohup")
+#[must_use]
+pub fn system(command: &str) -> Option<i32> {
+    let mut child = unblock_signals(&mut Command::new("nohup"))
         .args(["bash", "-c", &command])
         .stdout(std::process::Stdio::null())
         .stderr(std::process::Stdio::null())
         .spawn()
         .expect("");
+    let exit_status = child.wait().expect("Failed to wait for command");
+    exit_status.code()
 }
 
 fn main() -> Result<(), ()> {
-    if Path::new(SOCKET_PATH).exists() {
-        let _ = std::fs::remove_file(SOCKET_PATH);
-        thread::sleep(Duration::from_millis(20));
-    }
-
     let opts = Options::new();
+    let _ = PATHS.set(Paths::resolve_with(&opts.paths, read_path_keys));
+
+    replace_running_daemon();
 
     if opts.detach {
         match unsafe { fork().unwrap() } {
@@ -108,14 +117,54 @@ fn main() -> Result<(), ()> {
         }
     }
 
-    let mut daemon = Daemon::new(opts.init_path);
+    let mut daemon = Daemon::new(opts.init_path, opts.restore);
     daemon.mainloop();
     Ok(())
 }
 
+// the socket and cache keys of the parsed config, an invalid config sets neither
+fn read_path_keys(config_path: &str) -> PathKeys {
+    let Ok(caption) = fs::read_to_string(config_path) else {
+        return PathKeys::default();
+    };
+
+    match Config::from_string(&caption, ConfigFormat::from_path(config_path)) {
+        (Some(config), _) => PathKeys {
+            socket_path: config.socket_path,
+            cache_dir: config.cache_dir,
+        },
+        (None, _) => PathKeys::default(),
+    }
+}
+
+// asks a daemon which still listens on the socket to shut down and removes a stale socket file
+fn replace_running_daemon() {
+    let socket_path = &paths().socket;
+
+    if let Ok(mut stream) = UnixStream::connect(socket_path) {
+        if let Ok(message) = serde_json::to_string(&Request::new(vec![common::Command::Shutdown])) {
+            let _ = stream.write_all(format!("{message}\n").as_bytes());
+            let mut respond = String::new();
+            let _ = BufReader::new(&stream).read_line(&mut respond);
+        }
+        for _ in 0..100 {
+            if !Path::new(socket_path).exists() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    if Path::new(socket_path).exists() {
+        let _ = std::fs::remove_file(socket_path);
+    }
+}
+
 struct Options {
     detach: bool,
+    restore: bool,
     init_path: Option<String>,
+    paths: PathOverrides,
 }
 
 impl Options {
@@ -123,6 +172,7 @@ impl Options {
         let args = std::env::args().collect::<Vec<String>>();
 
         let detach = args.contains(&"-d".to_string());
+        let restore = args.contains(&"-r".to_string());
         let mut init_path: Option<String> = None;
 
         if let Some(arg) = take_argument_after("-i", &args) {
@@ -131,7 +181,12 @@ impl Options {
             }
         }
 
-        Options { detach, init_path }
+        Options {
+            detach,
+            restore,
+            init_path,
+            paths: PathOverrides::from_args(&args),
+        }
     }
 }
 
@@ -195,6 +195,39 @@ fn remove_comment(s: &str) -> String {
     s.to_string()
 }
 
+// paths of all files included by the template, nested includes are followed once
+pub fn collect_includes(path: &str) -> Vec<String> {
+    let mut res: Vec<String> = Vec::new();
+    let mut queue = vec![path.to_string()];
+
+    while let Some(path) = queue.pop() {
+        let Ok(raw_template) = fs::read_to_string(&path) else {
+            continue;
+        };
+
+        for line in raw_template.lines() {
+            if line.trim() == CONFIG_MARK {
+                break;
+            }
+            let Some(line) = validate_line(line) else {
+                continue;
+            };
+            if let Some(include) = line
+                .strip_prefix(INCLUDE_TAG)
+                .and_then(|l| l.strip_suffix(')'))
+            {
+                let include = expand_user(include);
+                if include != path && !res.contains(&include) {
+                    res.push(include.clone());
+                    queue.push(include);
+                }
+            }
+        }
+    }
+
+    res
+}
+
 fn apply_include(caption: Vec<String>) -> Vec<String> {
     let mut res: Vec<String> = Vec::new();
 
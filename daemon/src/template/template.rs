@@ -1,11 +1,15 @@
 use std::fs;
 use std::path;
 use std::str::FromStr;
-use std::thread;
 
 use crate::colorscheme::colors::color::Color;
 use crate::colorscheme::colorvariable::ColorValue;
+use crate::daemon::events::emit;
+use crate::daemon::pool::{pool, Priority};
+use crate::daemon::state;
+use crate::logger::logger::warn;
 use crate::{expand_user, system};
+use common::Event;
 
 use super::parser::collect_colors;
 use super::parser::collect_command;
@@ -126,7 +130,21 @@ impl Template {
             config = config.replace(&color_value.name, &format);
         }
 
-        let _ = fs::write(expand_user(&self.conf_path), config);
+        let conf_path = expand_user(&self.conf_path);
+        if let Err(e) = fs::write(&conf_path, config) {
+            warn(&format!(
+                "Unable to write {conf_path} from template {}: {e}",
+                self.self_path
+            ));
+            return;
+        }
+
+        state::update(|state| state.set_template(&self.self_path, &conf_path));
+        emit(&Event::TemplateWritten {
+            template: self.self_path.clone(),
+            path: conf_path,
+            exec_after: self.commands_after.clone(),
+        });
 
         self.exec_after();
     }
@@ -134,19 +152,23 @@ impl Template {
     fn exec_before(&self) {
         for command in &self.commands_before {
             if !command.is_empty() {
-                system(command);
+                let _ = system(command);
             }
         }
     }
 
     fn exec_after(&self) {
         let commands = self.commands_after.clone();
-        let _ = thread::Builder::new()
-            .name(format!("template {} exec_after", self.self_path))
-            .spawn(move || {
+        let template = self.self_path.clone();
+        pool().spawn(Priority::Background, move || {
             for command in &commands {
                 if !command.is_empty() {
-                    system(command);
+                    let status = system(command);
+                    emit(&Event::ExecAfterFinished {
+                        template: template.clone(),
+                        command: command.clone(),
+                        status,
+                    });
                 }
             }
         });
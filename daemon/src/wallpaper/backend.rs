@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+
+use crate::daemon::pool::{pool, Priority};
+use crate::daemon::state::{self, current_state, SetterState};
+use crate::logger::logger::info;
+use crate::unblock_signals;
+
+// long running setters, keyed by display name, the previous one is killed on every set. their
+// process groups are kept in the state, so the setters of a daemon which did not shut down
+// cleanly are killed by the next one
+static PROCESSES: Mutex<Option<HashMap<String, Setter>>> = Mutex::new(None);
+
+// a setter which fails exits right away, one still running after this is taken as set
+const SETTER_STARTUP: Duration = Duration::from_millis(300);
+// a replaced setter which ignores SIGTERM for this long is killed
+const SETTER_EXIT: Duration = Duration::from_secs(5);
+const SETTER_POLL: Duration = Duration::from_millis(20);
+
+enum Setter {
+    Spawned(Child),
+    // left behind by a previous daemon, it is not a child of this one
+    Adopted { pid: i32, started_at: u64 },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    Swaybg,
+    Swww,
+    Hyprpaper,
+    Feh,
+    Xwallpaper,
+    Command,
+}
+
+#[derive(Clone, Copy)]
+pub struct Wallpaper<'a> {
+    pub display: &'a str,
+    pub path: &'a str,
+    pub original_path: &'a str,
+}
+
+pub trait WallpaperBackend {
+    fn set(&self, wallpapers: &[Wallpaper]) -> Result<(), String>;
+}
+
+pub fn get_backend(kind: BackendKind, set_command: Option<&str>) -> Box<dyn WallpaperBackend> {
+    match kind {
+        BackendKind::Swaybg => Box::new(Swaybg),
+        BackendKind::Swww => Box::new(Swww),
+        BackendKind::Hyprpaper => Box::new(Hyprpaper),
+        BackendKind::Feh => Box::new(Feh),
+        BackendKind::Xwallpaper => Box::new(Xwallpaper),
+        BackendKind::Command => Box::new(SetCommand {
+            command: set_command.unwrap_or_default().to_string(),
+        }),
+    }
+}
+
+struct Swaybg;
+
+impl WallpaperBackend for Swaybg {
+    fn set(&self, wallpapers: &[Wallpaper]) -> Result<(), String> {
+        for wallpaper in wallpapers {
+            let mut command = Command::new("swaybg");
+            command.args(["-o", wallpaper.display, "-i", wallpaper.path, "-m", "fill"]);
+            replace_process(wallpaper.display, &mut command)?;
+        }
+        Ok(())
+    }
+}
+
+struct Swww;
+
+impl WallpaperBackend for Swww {
+    fn set(&self, wallpapers: &[Wallpaper]) -> Result<(), String> {
+        for wallpaper in wallpapers {
+            kill_process(wallpaper.display);
+            run(Command::new("swww").args(["img", "--outputs", wallpaper.display, wallpaper.path]))?;
+        }
+        Ok(())
+    }
+}
+
+struct Hyprpaper;
+
+impl WallpaperBackend for Hyprpaper {
+    fn set(&self, wallpapers: &[Wallpaper]) -> Result<(), String> {
+        for wallpaper in wallpapers {
+            kill_process(wallpaper.display);
+            run(Command::new("hyprctl").args(["hyprpaper", "preload", wallpaper.path]))?;
+            run(Command::new("hyprctl").args([
+                "hyprpaper",
+                "wallpaper",
+                &format!("{},{}", wallpaper.display, wallpaper.path),
+            ]))?;
+        }
+        run(Command::new("hyprctl").args(["hyprpaper", "unload", "unused"]))
+    }
+}
+
+// feh assigns images to xinerama screens in the order they are passed, the screens are the
+// monitors of xrandr in its order. feh sets every screen at once, images are repeated when
+// fewer are passed
+struct Feh;
+
+impl WallpaperBackend for Feh {
+    fn set(&self, wallpapers: &[Wallpaper]) -> Result<(), String> {
+        let monitors = xinerama_order();
+        let mut wallpapers = wallpapers.to_vec();
+        // displays unknown to xrandr keep their order after the known ones
+        wallpapers.sort_by_key(|wallpaper| {
+            monitors
+                .iter()
+                .position(|monitor| monitor == wallpaper.display)
+                .unwrap_or(usize::MAX)
+        });
+
+        let mut command = Command::new("feh");
+        command.args(["--no-fehbg", "--bg-fill"]);
+        for wallpaper in wallpapers {
+            kill_process(wallpaper.display);
+            command.arg(wallpaper.path);
+        }
+        run(&mut command)
+    }
+}
+
+// output names by monitor index, the lines of `xrandr --listmonitors` end with the name
+fn xinerama_order() -> Vec<String> {
+    let Ok(output) = unblock_signals(Command::new("xrandr").arg("--listmonitors"))
+        .stderr(Stdio::null())
+        .output()
+    else {
+        return Vec::new();
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().last().map(ToString::to_string))
+        .collect()
+}
+
+struct Xwallpaper;
+
+impl WallpaperBackend for Xwallpaper {
+    fn set(&self, wallpapers: &[Wallpaper]) -> Result<(), String> {
+        let mut command = Command::new("xwallpaper");
+        for wallpaper in wallpapers {
+            kill_process(wallpaper.display);
+            command.args(["--output", wallpaper.display, "--zoom", wallpaper.path]);
+        }
+        run(&mut command)
+    }
+}
+
+// user defined shell command with {image}, {default_image} and {display} placeholders
+struct SetCommand {
+    command: String,
+}
+
+impl WallpaperBackend for SetCommand {
+    fn set(&self, wallpapers: &[Wallpaper]) -> Result<(), String> {
+        if self.command.is_empty() {
+            return Err("wall_command is not set".to_string());
+        }
+        for wallpaper in wallpapers {
+            let command = self
+                .command
+                .replace("{image}", wallpaper.path)
+                .replace("{default_image}", wallpaper.original_path)
+                .replace("{display}", wallpaper.display);
+            replace_process(wallpaper.display, Command::new("bash").args(["-c", &command]))?;
+        }
+        Ok(())
+    }
+}
+
+fn run(command: &mut Command) -> Result<(), String> {
+    let program = command.get_program().to_string_lossy().to_string();
+    let status = unblock_signals(command)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(|e| format!("unable to run {program}: {e}"))?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{program} exited with {status}"))
+    }
+}
+
+// the new setter is started before the old one is killed, so the display never goes blank.
+// a setter which fails keeps the old one
+fn replace_process(display: &str, command: &mut Command) -> Result<(), String> {
+    let program = command.get_program().to_string_lossy().to_string();
+    let mut child = unblock_signals(command)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()
+        .map_err(|e| format!("unable to run {program}: {e}"))?;
+
+    if let Some(status) = wait_startup(&mut child) {
+        if !status.success() {
+            return Err(format!("{program} exited with {status}"));
+        }
+    }
+
+    let mut processes = PROCESSES.lock().unwrap();
+    let processes = processes.get_or_insert_with(HashMap::new);
+    let previous = processes.insert(display.to_string(), Setter::Spawned(child));
+    save_setters(processes);
+    if let Some(previous) = previous {
+        kill(previous);
+    }
+    Ok(())
+}
+
+// the exit status of a setter which does not keep running
+fn wait_startup(child: &mut Child) -> Option<ExitStatus> {
+    let started = Instant::now();
+    while started.elapsed() < SETTER_STARTUP {
+        if let Ok(Some(status)) = child.try_wait() {
+            return Some(status);
+        }
+        thread::sleep(SETTER_POLL);
+    }
+    None
+}
+
+// used when a display switches to a backend which does not keep its own process
+fn kill_process(display: &str) {
+    let mut processes = PROCESSES.lock().unwrap();
+    let Some(processes) = processes.as_mut() else {
+        return;
+    };
+    if let Some(previous) = processes.remove(display) {
+        save_setters(processes);
+        kill(previous);
+    }
+}
+
+// setters of the previous daemon which are still running are killed once the wallpaper of
+// their display is set again, or by `kill_adopted_setters`
+pub fn adopt_setters() {
+    let mut processes = PROCESSES.lock().unwrap();
+    let processes = processes.get_or_insert_with(HashMap::new);
+    for setter in current_state().setters {
+        if process_started_at(setter.pid) == Some(setter.started_at) {
+            processes
+                .entry(setter.display)
+                .or_insert(Setter::Adopted {
+                    pid: setter.pid,
+                    started_at: setter.started_at,
+                });
+        }
+    }
+    save_setters(processes);
+}
+
+pub fn kill_adopted_setters() {
+    let mut processes = PROCESSES.lock().unwrap();
+    let Some(processes) = processes.as_mut() else {
+        return;
+    };
+    let adopted: Vec<String> = processes
+        .iter()
+        .filter(|(_, setter)| matches!(setter, Setter::Adopted { .. }))
+        .map(|(display, _)| display.clone())
+        .collect();
+    if adopted.is_empty() {
+        return;
+    }
+    info(&format!(
+        "Stopping {} wallpaper setters of the previous daemon.",
+        adopted.len()
+    ));
+    for display in adopted {
+        if let Some(setter) = processes.remove(&display) {
+            kill(setter);
+        }
+    }
+    save_setters(processes);
+}
+
+// on shutdown, setters do not outlive the daemon
+pub fn kill_setters() {
+    let mut processes = PROCESSES.lock().unwrap();
+    let Some(processes) = processes.as_mut() else {
+        return;
+    };
+    for (_, setter) in processes.drain() {
+        kill(setter);
+    }
+    save_setters(processes);
+}
+
+fn save_setters(processes: &HashMap<String, Setter>) {
+    let setters: Vec<SetterState> = processes
+        .iter()
+        .filter_map(|(display, setter)| {
+            let pid = match setter {
+                Setter::Spawned(child) => i32::try_from(child.id()).ok()?,
+                Setter::Adopted { pid, .. } => *pid,
+            };
+            Some(SetterState {
+                display: display.clone(),
+                pid,
+                started_at: process_started_at(pid)?,
+            })
+        })
+        .collect();
+    state::update(|state| state.setters = setters);
+}
+
+// start time of a process in clock ticks after boot, a reused pid has another one. only
+// group leaders are setters, the ones left behind lead their own group
+fn process_started_at(pid: i32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // the command name may contain spaces and parentheses, the fields follow the last one
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let group: i32 = fields.get(2)?.parse().ok()?;
+    if group != pid {
+        return None;
+    }
+    fields.get(19)?.parse().ok()
+}
+
+fn kill(setter: Setter) {
+    let mut child = match setter {
+        Setter::Spawned(child) => child,
+        // the pid may have been reused since
+        Setter::Adopted { pid, started_at } => {
+            if process_started_at(pid) == Some(started_at) {
+                let _ = killpg(Pid::from_raw(pid), Signal::SIGTERM);
+            }
+            return;
+        }
+    };
+    let Ok(None) = child.try_wait() else {
+        return;
+    };
+    let Ok(pid) = i32::try_from(child.id()) else {
+        return;
+    };
+    let _ = killpg(Pid::from_raw(pid), Signal::SIGTERM);
+
+    // reaped in the background, a setter is not trusted to exit right away
+    pool().spawn(Priority::Background, move || {
+        let started = Instant::now();
+        while let Ok(None) = child.try_wait() {
+            if started.elapsed() > SETTER_EXIT {
+                let _ = killpg(Pid::from_raw(pid), Signal::SIGKILL);
+                let _ = child.wait();
+                return;
+            }
+            thread::sleep(SETTER_POLL);
+        }
+    });
+}
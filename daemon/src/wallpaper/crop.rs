@@ -0,0 +1,201 @@
+use std::str::FromStr;
+
+use image::imageops::FilterType;
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+
+// smart crop measures detail on a copy no bigger than this
+const SMART_SIZE: u32 = 256;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Gravity {
+    #[default]
+    Center,
+    Top,
+    Bottom,
+    Left,
+    Right,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    // keeps the region with the most detail
+    Smart,
+}
+
+impl FromStr for Gravity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "center" => Ok(Gravity::Center),
+            "top" => Ok(Gravity::Top),
+            "bottom" => Ok(Gravity::Bottom),
+            "left" => Ok(Gravity::Left),
+            "right" => Ok(Gravity::Right),
+            "top-left" => Ok(Gravity::TopLeft),
+            "top-right" => Ok(Gravity::TopRight),
+            "bottom-left" => Ok(Gravity::BottomLeft),
+            "bottom-right" => Ok(Gravity::BottomRight),
+            "smart" => Ok(Gravity::Smart),
+            _ => Err(format!("unknown gravity `{s}`")),
+        }
+    }
+}
+
+impl Gravity {
+    fn name(self) -> &'static str {
+        match self {
+            Gravity::Center => "center",
+            Gravity::Top => "top",
+            Gravity::Bottom => "bottom",
+            Gravity::Left => "left",
+            Gravity::Right => "right",
+            Gravity::TopLeft => "top-left",
+            Gravity::TopRight => "top-right",
+            Gravity::BottomLeft => "bottom-left",
+            Gravity::BottomRight => "bottom-right",
+            Gravity::Smart => "smart",
+        }
+    }
+
+    // share of the overflow which is cut from the left and from the top
+    fn fraction(self) -> (f32, f32) {
+        match self {
+            Gravity::Center | Gravity::Smart => (0.5, 0.5),
+            Gravity::Top => (0.5, 0.0),
+            Gravity::Bottom => (0.5, 1.0),
+            Gravity::Left => (0.0, 0.5),
+            Gravity::Right => (1.0, 0.5),
+            Gravity::TopLeft => (0.0, 0.0),
+            Gravity::TopRight => (1.0, 0.0),
+            Gravity::BottomLeft => (0.0, 1.0),
+            Gravity::BottomRight => (1.0, 1.0),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Anchor {
+    Gravity(Gravity),
+    // the point of the image, as a fraction of its size, which is kept in the middle
+    Focal(f32, f32),
+}
+
+impl Default for Anchor {
+    fn default() -> Self {
+        Anchor::Gravity(Gravity::default())
+    }
+}
+
+impl Anchor {
+    pub fn key(self) -> String {
+        match self {
+            Anchor::Gravity(gravity) => gravity.name().to_string(),
+            Anchor::Focal(x, y) => format!("focal:{x}:{y}"),
+        }
+    }
+}
+
+// top left corner of the area of `image` which is kept when it is cut down to the layout size
+pub fn crop_offset(
+    image: &DynamicImage,
+    anchor: Anchor,
+    (width, height): (u32, u32),
+) -> (u32, u32) {
+    let overflow_x = image.width().saturating_sub(width);
+    let overflow_y = image.height().saturating_sub(height);
+    if overflow_x == 0 && overflow_y == 0 {
+        return (0, 0);
+    }
+
+    match anchor {
+        Anchor::Gravity(Gravity::Smart) => smart_offset(image, (width, height)),
+        Anchor::Gravity(gravity) => {
+            let (x, y) = gravity.fraction();
+            (share(overflow_x, x), share(overflow_y, y))
+        }
+        Anchor::Focal(x, y) => (
+            centered_on(image.width(), width, x),
+            centered_on(image.height(), height, y),
+        ),
+    }
+}
+
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss
+)]
+fn share(overflow: u32, fraction: f32) -> u32 {
+    (overflow as f32 * fraction.clamp(0.0, 1.0)).round() as u32
+}
+
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss
+)]
+fn centered_on(size: u32, window: u32, fraction: f32) -> u32 {
+    let start = size as f32 * fraction.clamp(0.0, 1.0) - window as f32 / 2.0;
+    (start.max(0.0) as u32).min(size.saturating_sub(window))
+}
+
+// picks the window with the highest edge density, measured on a small grayscale copy
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss
+)]
+fn smart_offset(image: &DynamicImage, (width, height): (u32, u32)) -> (u32, u32) {
+    let scale = (SMART_SIZE as f32 / image.width().max(image.height()) as f32).min(1.0);
+    let small_w = ((image.width() as f32 * scale) as u32).max(1);
+    let small_h = ((image.height() as f32 * scale) as u32).max(1);
+    let small = image
+        .resize_exact(small_w, small_h, FilterType::Triangle)
+        .to_luma8();
+
+    let (w, h) = (small_w as usize, small_h as usize);
+    // summed area table of the gradient magnitude, one row and column of padding
+    let mut integral = vec![0u64; (w + 1) * (h + 1)];
+    for y in 0..h {
+        for x in 0..w {
+            let pixel = i32::from(small.get_pixel(x as u32, y as u32)[0]);
+            let right = i32::from(small.get_pixel((x + 1).min(w - 1) as u32, y as u32)[0]);
+            let below = i32::from(small.get_pixel(x as u32, (y + 1).min(h - 1) as u32)[0]);
+            let edge = u64::from((pixel - right).unsigned_abs() + (pixel - below).unsigned_abs());
+            integral[(y + 1) * (w + 1) + x + 1] =
+                edge + integral[y * (w + 1) + x + 1] + integral[(y + 1) * (w + 1) + x]
+                    - integral[y * (w + 1) + x];
+        }
+    }
+    let sum = |x: usize, y: usize, cw: usize, ch: usize| {
+        integral[(y + ch) * (w + 1) + x + cw] + integral[y * (w + 1) + x]
+            - integral[y * (w + 1) + x + cw]
+            - integral[(y + ch) * (w + 1) + x]
+    };
+
+    let window_w = ((width as f32 * scale).round() as usize).clamp(1, w);
+    let window_h = ((height as f32 * scale).round() as usize).clamp(1, h);
+    let (center_x, center_y) = ((w - window_w) / 2, (h - window_h) / 2);
+
+    // equally detailed windows are resolved towards the middle of the image
+    let mut best = (0, 0);
+    let mut best_score = (0, usize::MAX);
+    for y in 0..=h - window_h {
+        for x in 0..=w - window_w {
+            let distance = x.abs_diff(center_x) + y.abs_diff(center_y);
+            let detail = sum(x, y, window_w, window_h);
+            if detail > best_score.0 || (detail == best_score.0 && distance < best_score.1) {
+                best = (x, y);
+                best_score = (detail, distance);
+            }
+        }
+    }
+
+    (
+        ((best.0 as f32 / scale) as u32).min(image.width().saturating_sub(width)),
+        ((best.1 as f32 / scale) as u32).min(image.height().saturating_sub(height)),
+    )
+}
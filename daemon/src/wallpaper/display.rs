@@ -1,35 +1,119 @@
 use std::path::Path;
-use std::thread;
+use std::sync::Arc;
 
+use image::DynamicImage;
+
+use crate::colorscheme::rwal::rwal::get_palette;
 use crate::daemon::config::Config;
-use crate::wallpaper::image::ImageOperations;
-use crate::{encode_string, expand_user, get_image_name, spawn, WALLPAPERS_DIR};
+use crate::daemon::events::emit;
+use crate::daemon::pool::{current_priority, pool};
+use crate::daemon::state;
+use crate::logger::logger::info;
+use crate::cache::decoded;
+use crate::cache::manifest::{self, CacheKind, Pinned};
+use crate::cache::source::source_key;
+use crate::{encode_string, expand_user, get_image_name, paths};
 use common::display::Display;
+use common::Event;
 
-use super::image::get_image;
+use super::backend::{get_backend, BackendKind, Wallpaper};
+use super::fit::{fit_image, parse_hex, scale_image, Fit, FitMode};
+use super::image::{get_thumbed_image, resize_filter};
 
-pub fn displays_max_width(displays: &Vec<Display>) -> u32 {
-    let mut res: u32 = 0;
-    for display in displays {
-        if display.w + display.x > res {
-            res = display.w + display.x
-        }
+// where a display is drawn on the rendered layout, in logical pixels
+#[derive(Clone, Copy)]
+pub struct Placement {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+// places displays on a layout starting at the origin, negative positions are shifted and
+// bezels push apart the displays behind them, so an image continues across the frames
+pub fn layout(displays: &[Display]) -> (Vec<Placement>, (u32, u32)) {
+    if displays.is_empty() {
+        return (Vec::new(), (0, 0));
     }
+    let sizes: Vec<(u32, u32)> = displays.iter().map(Display::logical_size).collect();
+    let positions: Vec<(i64, i64)> = displays
+        .iter()
+        .map(|display| {
+            let bezel = i64::from(display.bezel);
+            let (mut x, mut y) = (i64::from(display.x) + bezel, i64::from(display.y) + bezel);
+            for (other, (w, h)) in displays.iter().zip(&sizes) {
+                let frame = 2 * i64::from(other.bezel);
+                if i64::from(other.x) + i64::from(*w) <= i64::from(display.x) {
+                    x += frame;
+                }
+                if i64::from(other.y) + i64::from(*h) <= i64::from(display.y) {
+                    y += frame;
+                }
+            }
+            (x, y)
+        })
+        .collect();
 
-    res
+    let frames = displays
+        .iter()
+        .zip(&positions)
+        .zip(&sizes)
+        .map(|((display, (x, y)), (w, h))| {
+            let bezel = i64::from(display.bezel);
+            (
+                x - bezel,
+                y - bezel,
+                x + i64::from(*w) + bezel,
+                y + i64::from(*h) + bezel,
+            )
+        });
+    let (min_x, min_y, max_x, max_y) = frames.fold(
+        (i64::MAX, i64::MAX, i64::MIN, i64::MIN),
+        |(min_x, min_y, max_x, max_y), (left, top, right, bottom)| {
+            (
+                min_x.min(left),
+                min_y.min(top),
+                max_x.max(right),
+                max_y.max(bottom),
+            )
+        },
+    );
+    let to_u32 = |value: i64| u32::try_from(value).unwrap_or(u32::MAX);
+    let placements = positions
+        .iter()
+        .zip(&sizes)
+        .map(|((x, y), (w, h))| Placement {
+            x: to_u32(x - min_x),
+            y: to_u32(y - min_y),
+            w: *w,
+            h: *h,
+        })
+        .collect();
+    (placements, (to_u32(max_x - min_x), to_u32(max_y - min_y)))
 }
 
-pub fn displays_max_height(displays: &Vec<Display>) -> u32 {
-    let mut res: u32 = 0;
-    for display in displays {
-        if display.h + display.y > res {
-            res = display.h + display.y
-        }
-    }
+// the layout is rendered at the highest scale among the displays, so none of them is upscaled
+fn render_scale(displays: &[Display]) -> f32 {
+    displays
+        .iter()
+        .map(|display| display.scale)
+        .fold(1.0, f32::max)
+}
 
-    res
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss
+)]
+fn scaled(value: u32, scale: f32) -> u32 {
+    (value as f32 * scale).round() as u32
 }
 
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss
+)]
 pub fn calculate_width_height(
     image_width: u32,
     image_height: u32,
@@ -41,14 +125,14 @@ pub fn calculate_width_height(
     let mut width: f32 = image_width as f32 * w_diff;
     let mut height: f32 = image_height as f32 * w_diff;
 
-    let h_diff: f32 = max_height as f32 / height as f32;
+    let h_diff: f32 = max_height as f32 / height;
 
     if h_diff > 1.0 {
-        width = width * h_diff;
-        height = height * h_diff;
+        width *= h_diff;
+        height *= h_diff;
     }
 
-    return (width as u32, height as u32);
+    (width as u32, height as u32)
 }
 
 fn get_file_extension(file_name: &str) -> &str {
@@ -61,24 +145,40 @@ fn get_file_extension(file_name: &str) -> &str {
 }
 
 pub fn get_cached_image_names(
+    config: &Config,
     displays: &Vec<Display>,
-    image_ops: &ImageOperations,
     image_path: &str,
-) -> Vec<String> {
+) -> Result<Vec<String>, String> {
+    let Some(image_ops) = &config.image_operations else {
+        return Ok(Vec::new());
+    };
+    let source = source_key(image_path)?;
+    let encoding = config.cache_encoding.unwrap_or_default();
     let image_name = get_image_name(image_path);
-    let image_extension = get_file_extension(&image_name);
+    let image_extension = encoding.extension(get_file_extension(&image_name));
+    // the slices depend on the whole layout, a display set on its own is scaled differently
+    let (_, (layout_width, layout_height)) = layout(displays);
     let mut cached_images: Vec<String> = Vec::new();
     for display in displays {
         cached_images.push(format!(
             "{}.{}",
             encode_string(&format!(
-                "{}{}{}{}{}{}{}{}{}{}{}{}{}",
-                image_name,
+                "{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}",
+                source,
+                encoding.key(),
+                config.active_profile.as_deref().unwrap_or_default(),
+                layout_width,
+                layout_height,
                 display.name,
                 display.w,
                 display.h,
                 display.x,
                 display.y,
+                display.scale,
+                display.transform.name(),
+                display.bezel,
+                config.fit_for(&display.name).key(),
+                config.resize_algorithm.as_deref().unwrap_or_default(),
                 image_ops.contrast,
                 image_ops.brightness,
                 image_ops.hue,
@@ -88,88 +188,324 @@ pub fn get_cached_image_names(
                 image_ops.flip_v,
             )),
             image_extension
-        ))
+        ));
     }
-    cached_images
+    Ok(cached_images)
 }
 
 pub fn get_cached_image_paths(cache_images: &Vec<String>, cache_path: &str) -> Vec<String> {
     let mut cache_paths: Vec<String> = Vec::new();
 
     for cache_image in cache_images {
-        cache_paths.push(format!("{}/{}", cache_path, cache_image,))
+        cache_paths.push(format!("{cache_path}/{cache_image}"));
     }
 
     cache_paths
 }
 
-pub fn cache_wallpaper(config: &Config, image_path: &str) {
+pub fn cache_wallpaper(config: &Config, image_path: &str) -> Result<(), String> {
     if let Some(displays) = &config.displays {
         if let Some(image_ops) = &config.image_operations {
             if let Some(image_resize_algorithm) = &config.resize_algorithm {
-                let cache_paths = get_cached_image_paths(
-                    &get_cached_image_names(displays, image_ops, image_path),
-                    WALLPAPERS_DIR,
-                );
+                let cache_paths: Vec<String> = get_cached_image_paths(
+                    &get_cached_image_names(config, displays, image_path)?,
+                    &paths().wallpapers_dir(),
+                )
+                .iter()
+                .map(|path| expand_user(path))
+                .collect();
 
-                let image = get_image(image_path, image_ops, displays, image_resize_algorithm);
+                let original = decoded::original(image_path)?;
+                let filter = resize_filter(image_resize_algorithm);
+                let encoding = config.cache_encoding.unwrap_or_default();
+                let (placements, (layout_width, layout_height)) = layout(displays);
+                let scale = render_scale(displays);
+                let canvas_size = (scaled(layout_width, scale), scaled(layout_height, scale));
+                let palette_color = displays
+                    .iter()
+                    .any(|display| config.fit_for(&display.name).uses_palette())
+                    .then(|| palette_background(config, image_path))
+                    .flatten();
 
-                let mut handlers = Vec::new();
+                // displays sharing a fit are cropped from the same rendered layout
+                let mut canvases: Vec<(Fit, Arc<DynamicImage>)> = Vec::new();
+                let mut tasks: Vec<Box<dyn FnOnce() -> Result<(), String> + Send>> = Vec::new();
 
-                for (i, cache_path) in cache_paths.into_iter().enumerate() {
+                for (i, cache_path) in cache_paths.iter().cloned().enumerate() {
                     let display = displays[i].clone();
-                    let cache_path = String::from(cache_path);
-                    let mut image = image.clone();
-                    let thread = thread::spawn(move || {
-                        image = image.crop_imm(display.x, display.y, display.w, display.h);
-                        let _ = image.save(expand_user(&cache_path));
-                        std::mem::drop(image);
-                    });
-                    handlers.push(thread)
+                    let placement = placements[i];
+                    let fit = config.fit_for(&display.name);
+                    let canvas =
+                        if let Some((_, canvas)) = canvases.iter().find(|(f, _)| *f == fit) {
+                            canvas.clone()
+                        } else {
+                            let base = scaled_image(
+                                image_path,
+                                &original,
+                                fit.mode,
+                                canvas_size,
+                                image_resize_algorithm,
+                            )?;
+                            let canvas = Arc::new(fit_image(
+                                &original,
+                                &base,
+                                image_ops,
+                                fit,
+                                palette_color,
+                                canvas_size,
+                            ));
+                            canvases.push((fit, canvas.clone()));
+                            canvas
+                        };
+                    tasks.push(Box::new(move || {
+                        // cropped from the logical layout, saved at the resolution of the display
+                        let mut image = canvas.crop_imm(
+                            scaled(placement.x, scale),
+                            scaled(placement.y, scale),
+                            scaled(placement.w, scale),
+                            scaled(placement.h, scale),
+                        );
+                        let (width, height) = display.physical_size();
+                        if (image.width(), image.height()) != (width, height) {
+                            image = image.resize_exact(width, height, filter);
+                        }
+                        encoding.save(&image, &cache_path)
+                    }));
                 }
 
-                for handler in handlers {
-                    handler.join().unwrap();
+                let errors: Vec<String> = pool()
+                    .run(current_priority(), tasks)?
+                    .into_iter()
+                    .filter_map(Result::err)
+                    .collect();
+                manifest::record(
+                    config,
+                    image_path,
+                    CacheKind::Wallpaper,
+                    Some(displays),
+                    &cache_paths,
+                );
+                if !errors.is_empty() {
+                    return Err(errors.join("; "));
                 }
             }
         }
     }
+    Ok(())
 }
 
-fn parse_set_command(
-    command: &str,
+// scaled images are kept in memory by the size of the layout, so when only the image
+// operations change the original is neither decoded nor scaled again
+fn scaled_image(
     image_path: &str,
-    original_image_path: &str,
-    display: &str,
-) -> String {
-    command
-        .replace("{image}", &expand_user(image_path))
-        .replace("{default_image}", original_image_path)
-        .replace("{display}", display)
+    original: &Arc<DynamicImage>,
+    mode: FitMode,
+    (width, height): (u32, u32),
+    resize_algorithm: &str,
+) -> Result<Arc<DynamicImage>, String> {
+    if !mode.scales() {
+        return Ok(original.clone());
+    }
+    let variant = format!("{}:{width}x{height}:{resize_algorithm}", mode.name());
+    decoded::derived(image_path, &variant, |image| {
+        scale_image(image, mode, (width, height), resize_filter(resize_algorithm))
+    })
 }
 
-pub fn set_wallpaper(config: &Config, image_path: &str) {
-    if let Some(displays) = &config.displays {
-        if let Some(image_ops) = &config.image_operations {
-            if let Some(set_command) = &config.set_command {
-                let cache_paths = get_cached_image_paths(
-                    &get_cached_image_names(displays, image_ops, image_path),
-                    WALLPAPERS_DIR,
-                );
+pub fn set_wallpaper(config: &Config, image_path: &str) -> Result<(), String> {
+    let (Some(displays), Some(image_ops)) = (&config.displays, &config.image_operations) else {
+        return Ok(());
+    };
 
-                for cache_path in &cache_paths {
-                    if !Path::new(&expand_user(cache_path)).exists() {
-                        cache_wallpaper(config, image_path);
-                        break;
-                    }
-                }
+    let cache_paths: Vec<String> = get_cached_image_paths(
+        &get_cached_image_names(config, displays, image_path)?,
+        &paths().wallpapers_dir(),
+    )
+    .iter()
+    .map(|path| expand_user(path))
+    .collect();
 
-                for (i, cache_path) in cache_paths.into_iter().enumerate() {
-                    let command =
-                        parse_set_command(set_command, &cache_path, image_path, &displays[i].name);
-                    spawn(&command);
-                }
-            }
+    let _pinned = manifest::pin(&cache_paths);
+    if cache_paths.iter().any(|path| !Path::new(path).exists()) {
+        cache_wallpaper(config, image_path)?;
+    } else {
+        manifest::record(
+            config,
+            image_path,
+            CacheKind::Wallpaper,
+            Some(displays),
+            &cache_paths,
+        );
+    }
+
+    let wallpapers: Vec<Wallpaper> = displays
+        .iter()
+        .zip(&cache_paths)
+        .map(|(display, cache_path)| Wallpaper {
+            display: &display.name,
+            path: cache_path,
+            original_path: image_path,
+        })
+        .collect();
+    if !apply_backends(config, &wallpapers)? {
+        return Ok(());
+    }
+
+    state::update(|state| {
+        state.image = Some(image_path.to_string());
+        state.image_operations = Some(image_ops.clone());
+        state.displays.clear();
+        for (display, cache_path) in displays.iter().zip(&cache_paths) {
+            state.set_display(display, image_path, cache_path);
         }
+    });
+    Ok(())
+}
+
+pub fn cache_display_wallpapers(
+    config: &Config,
+    images: &[(Display, String)],
+) -> Result<(), String> {
+    for (display, image_path) in images {
+        cache_single_display(config, display, image_path, true)?;
+    }
+    Ok(())
+}
+
+// every display gets its own image, the images are not sliced from one spanning picture
+pub fn set_display_wallpapers(config: &Config, images: &[(Display, String)]) -> Result<(), String> {
+    let mut cache_paths = Vec::new();
+    let mut pinned = Vec::new();
+    for (display, image_path) in images {
+        let (cache_path, pin) = cache_single_display(config, display, image_path, false)?;
+        cache_paths.push(cache_path);
+        pinned.push(pin);
+    }
+
+    let wallpapers: Vec<Wallpaper> = images
+        .iter()
+        .zip(&cache_paths)
+        .map(|((display, image_path), cache_path)| Wallpaper {
+            display: &display.name,
+            path: cache_path,
+            original_path: image_path,
+        })
+        .collect();
+    if !apply_backends(config, &wallpapers)? {
+        return Ok(());
+    }
+
+    state::update(|state| {
+        state.image = None;
+        state.image_operations.clone_from(&config.image_operations);
+        state.displays.clear();
+        for ((display, image_path), cache_path) in images.iter().zip(&cache_paths) {
+            state.set_display(display, image_path, cache_path);
+        }
+    });
+    Ok(())
+}
+
+// the image is scaled and cropped for this display alone, as if it was the only one at the origin,
+// the crop stays pinned while the returned guard lives
+fn cache_single_display(
+    config: &Config,
+    display: &Display,
+    image_path: &str,
+    overwrite: bool,
+) -> Result<(String, Pinned), String> {
+    if config.image_operations.is_none() {
+        return Err("image operations are not configured".to_string());
+    }
+
+    let displays = vec![Display {
+        x: 0,
+        y: 0,
+        bezel: 0,
+        ..display.clone()
+    }];
+    let cache_path = expand_user(&format!(
+        "{}/{}",
+        paths().wallpapers_dir(),
+        get_cached_image_names(config, &displays, image_path)?[0]
+    ));
+
+    let pinned = manifest::pin(std::slice::from_ref(&cache_path));
+    if overwrite || !Path::new(&cache_path).exists() {
+        let mut config = config.clone();
+        config.displays = Some(displays);
+        cache_wallpaper(&config, image_path)?;
+    } else {
+        manifest::record(
+            config,
+            image_path,
+            CacheKind::Wallpaper,
+            Some(&displays),
+            std::slice::from_ref(&cache_path),
+        );
+    }
+    Ok((cache_path, pinned))
+}
+
+// the darkest color of the palette rwal would generate for the image
+fn palette_background(config: &Config, image_path: &str) -> Option<[u8; 3]> {
+    let (Some(image_ops), Some(rwal_params)) = (&config.image_operations, &config.rwal_params)
+    else {
+        return None;
+    };
+    let thumb = get_thumbed_image(
+        image_path,
+        image_ops,
+        rwal_params.thumb_range.0,
+        rwal_params.thumb_range.1,
+    )
+    .ok()?;
+    let palette = get_palette(
+        &thumb,
+        rwal_params.accent_color,
+        rwal_params.clamp_range,
+        rwal_params.order,
+    );
+    palette.first().and_then(|hex| parse_hex(hex))
+}
+
+// displays are grouped by backend, so backends which set every screen at once get all of them.
+// returns whether a backend was run, without one the wallpapers are only cached
+fn apply_backends(config: &Config, wallpapers: &[Wallpaper]) -> Result<bool, String> {
+    let mut groups: Vec<(BackendKind, Vec<Wallpaper>)> = Vec::new();
+    for wallpaper in wallpapers {
+        let Some(kind) = config.backend_for(wallpaper.display) else {
+            continue;
+        };
+        match groups.iter_mut().find(|(k, _)| *k == kind) {
+            Some((_, group)) => group.push(*wallpaper),
+            None => groups.push((kind, vec![*wallpaper])),
+        }
+    }
+
+    if groups.is_empty() {
+        info("No wallpaper backend is configured, the wallpaper was only cached.");
+        return Ok(false);
+    }
+
+    let mut errors = Vec::new();
+    for (kind, group) in groups {
+        if let Err(e) = get_backend(kind, config.set_command.as_deref()).set(&group) {
+            errors.push(e);
+            continue;
+        }
+        for wallpaper in group {
+            emit(&Event::WallpaperSet {
+                display: wallpaper.display.to_string(),
+                image: wallpaper.original_path.to_string(),
+                path: wallpaper.path.to_string(),
+            });
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(true)
+    } else {
+        Err(errors.join("; "))
     }
 }
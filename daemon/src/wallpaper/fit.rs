@@ -0,0 +1,241 @@
+use std::str::FromStr;
+
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use super::crop::{crop_offset, Anchor};
+use super::display::calculate_width_height;
+use super::image::{apply_image_ops, ImageOperations};
+
+// the blurred background is rendered at a fraction of the layout size and scaled up
+const BLUR_DOWNSCALE: u32 = 16;
+const BLUR_SIGMA: f32 = 4.0;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FitMode {
+    // covers the whole layout, the overflow is cropped around the anchor
+    #[default]
+    #[serde(alias = "cover")]
+    Fill,
+    // the whole image is visible, the rest of the layout is filled with the background
+    Contain,
+    // not scaled, placed in the middle of the layout over the background
+    Center,
+    // not scaled, repeated from the top left corner
+    Tile,
+    // scaled to the layout ignoring the aspect ratio
+    Stretch,
+}
+
+impl FromStr for FitMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fill" | "cover" => Ok(FitMode::Fill),
+            "contain" => Ok(FitMode::Contain),
+            "center" => Ok(FitMode::Center),
+            "tile" => Ok(FitMode::Tile),
+            "stretch" => Ok(FitMode::Stretch),
+            _ => Err(format!(
+                "unknown fit mode `{s}`, expected one of fill, contain, center, tile, stretch"
+            )),
+        }
+    }
+}
+
+impl FitMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            FitMode::Fill => "fill",
+            FitMode::Contain => "contain",
+            FitMode::Center => "center",
+            FitMode::Tile => "tile",
+            FitMode::Stretch => "stretch",
+        }
+    }
+
+    // center and tile place the image as it is
+    pub fn scales(self) -> bool {
+        matches!(self, FitMode::Fill | FitMode::Contain | FitMode::Stretch)
+    }
+}
+
+// written as `blur`, `palette` or a `#rrggbb` color
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Background {
+    #[default]
+    Blur,
+    // the background color of the palette generated from the image
+    Palette,
+    Color([u8; 3]),
+}
+
+impl FromStr for Background {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blur" => Ok(Background::Blur),
+            "palette" => Ok(Background::Palette),
+            _ => parse_hex(s).map(Background::Color).ok_or_else(|| {
+                format!("unknown background `{s}`, expected blur, palette or a #rrggbb color")
+            }),
+        }
+    }
+}
+
+impl TryFrom<String> for Background {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Background> for String {
+    fn from(background: Background) -> Self {
+        match background {
+            Background::Blur => "blur".to_string(),
+            Background::Palette => "palette".to_string(),
+            Background::Color([r, g, b]) => format!("#{r:02x}{g:02x}{b:02x}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Fit {
+    pub mode: FitMode,
+    pub background: Background,
+    pub anchor: Anchor,
+}
+
+impl Fit {
+    // the background only matters for modes which may leave parts of the layout uncovered
+    // and the anchor only for the mode which crops
+    pub fn key(self) -> String {
+        match self.mode {
+            FitMode::Contain | FitMode::Center => {
+                format!("{}:{}", self.mode.name(), String::from(self.background))
+            }
+            FitMode::Fill => format!("{}:{}", self.mode.name(), self.anchor.key()),
+            _ => self.mode.name().to_string(),
+        }
+    }
+
+    pub fn uses_palette(self) -> bool {
+        self.background == Background::Palette
+            && matches!(self.mode, FitMode::Contain | FitMode::Center)
+    }
+}
+
+// the image scaled for the layout, before it is cropped and the image operations are applied,
+// so it can be reused when only the operations change
+pub fn scale_image(
+    image: &DynamicImage,
+    mode: FitMode,
+    (width, height): (u32, u32),
+    filter: FilterType,
+) -> DynamicImage {
+    match mode {
+        FitMode::Fill => {
+            let (nw, nh) = calculate_width_height(image.width(), image.height(), width, height);
+            image.resize(nw, nh, filter)
+        }
+        FitMode::Stretch => image.resize_exact(width, height, filter),
+        FitMode::Contain => image.resize(width, height, filter),
+        FitMode::Center | FitMode::Tile => image.clone(),
+    }
+}
+
+// renders the image onto a canvas of the layout size, `scaled` is the image passed through
+// `scale_image` with the same mode and size
+pub fn fit_image(
+    image: &DynamicImage,
+    scaled: &DynamicImage,
+    image_ops: &ImageOperations,
+    fit: Fit,
+    palette_color: Option<[u8; 3]>,
+    (width, height): (u32, u32),
+) -> DynamicImage {
+    let canvas = match fit.mode {
+        FitMode::Fill => {
+            let (x, y) = crop_offset(scaled, fit.anchor, (width, height));
+            return apply_image_ops(scaled.crop_imm(x, y, width, height), image_ops);
+        }
+        FitMode::Stretch => {
+            return apply_image_ops(scaled.clone(), image_ops);
+        }
+        FitMode::Contain => {
+            let scaled = apply_image_ops(scaled.clone(), image_ops);
+            let mut canvas = background(image, image_ops, fit, palette_color, (width, height));
+            place_centered(&mut canvas, &scaled);
+            canvas
+        }
+        FitMode::Center => {
+            let processed = apply_image_ops(image.clone(), image_ops);
+            let mut canvas = background(image, image_ops, fit, palette_color, (width, height));
+            place_centered(&mut canvas, &processed);
+            canvas
+        }
+        FitMode::Tile => {
+            let processed = apply_image_ops(image.clone(), image_ops).to_rgba8();
+            let mut canvas = RgbaImage::new(width, height);
+            imageops::tile(&mut canvas, &processed);
+            canvas
+        }
+    };
+
+    // jpeg and a few other formats can not store an alpha channel
+    if image.color().has_alpha() {
+        DynamicImage::ImageRgba8(canvas)
+    } else {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(canvas).to_rgb8())
+    }
+}
+
+fn background(
+    image: &DynamicImage,
+    image_ops: &ImageOperations,
+    fit: Fit,
+    palette_color: Option<[u8; 3]>,
+    (width, height): (u32, u32),
+) -> RgbaImage {
+    match fit.background {
+        Background::Blur => {
+            let small = image.resize_to_fill(
+                (width / BLUR_DOWNSCALE).max(1),
+                (height / BLUR_DOWNSCALE).max(1),
+                FilterType::Triangle,
+            );
+            apply_image_ops(small, image_ops)
+                .blur(BLUR_SIGMA)
+                .resize_exact(width, height, FilterType::Triangle)
+                .to_rgba8()
+        }
+        Background::Palette => solid(palette_color.unwrap_or_default(), width, height),
+        Background::Color(color) => solid(color, width, height),
+    }
+}
+
+fn place_centered(canvas: &mut RgbaImage, image: &DynamicImage) {
+    let x = (i64::from(canvas.width()) - i64::from(image.width())) / 2;
+    let y = (i64::from(canvas.height()) - i64::from(image.height())) / 2;
+    imageops::overlay(canvas, &image.to_rgba8(), x, y);
+}
+
+fn solid([r, g, b]: [u8; 3], width: u32, height: u32) -> RgbaImage {
+    RgbaImage::from_pixel(width, height, Rgba([r, g, b, 255]))
+}
+
+pub fn parse_hex(hex: &str) -> Option<[u8; 3]> {
+    let digits = hex.strip_prefix('#')?;
+    if digits.len() != 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&digits[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
@@ -1,9 +1,8 @@
 use image::{DynamicImage, RgbImage};
-use image::imageops::{CatmullRom, Gaussian, Lanczos3, Nearest, Triangle};
+use image::imageops::{CatmullRom, FilterType, Gaussian, Lanczos3, Nearest, Triangle};
 use serde::{Deserialize, Serialize};
-use common::display::Display;
 
-use super::display::{calculate_width_height, displays_max_height, displays_max_width};
+use crate::cache::decoded;
 
 pub fn apply_image_ops(mut image: DynamicImage, image_ops: &ImageOperations) -> DynamicImage {
     if image_ops.contrast != 0.0 {
@@ -30,48 +29,34 @@ pub fn apply_image_ops(mut image: DynamicImage, image_ops: &ImageOperations) ->
     image
 }
 
-pub fn get_thumbed_image(image_path: &str, image_ops: &ImageOperations, w: u32, h: u32) -> RgbImage {
-    if let Ok(mut image) = image::open(image_path) {
-        image = image.resize_exact(w, h, Nearest);
-        image = apply_image_ops(image, image_ops);
-        image.to_rgb8()
-    } else {
-        RgbImage::new(4, 4)
-    }
-}
-
-pub fn get_image(
-    img_path: &str,
+pub fn get_thumbed_image(
+    image_path: &str,
     image_ops: &ImageOperations,
-    displays: &Vec<Display>,
-    image_resize_algorithm: &str,
-) -> DynamicImage {
-    let displays_max_width: u32 = displays_max_width(&displays);
-    let displays_max_height: u32 = displays_max_height(&displays);
+    w: u32,
+    h: u32,
+) -> Result<RgbImage, String> {
+    // kept in memory like the scaled wallpapers, the palette of other image operations
+    // starts from the same thumb
+    let thumb = decoded::derived(image_path, &format!("thumb:{w}x{h}"), |image| {
+        image.resize_exact(w, h, Nearest)
+    })?;
+    Ok(apply_image_ops((*thumb).clone(), image_ops).to_rgb8())
+}
 
-    let img_ra = match image_resize_algorithm {
+pub fn resize_filter(image_resize_algorithm: &str) -> FilterType {
+    match image_resize_algorithm {
         "Nearest" => Nearest,
         "CatmullRom" => CatmullRom,
         "Gaussian" => Gaussian,
         "Lanczos3" => Lanczos3,
         _ => Triangle,
-    };
-
-    if let Ok(mut image) = image::open(img_path) {
-        let (nw, nh) = calculate_width_height(
-            image.width(),
-            image.height(),
-            displays_max_width,
-            displays_max_height,
-        );
-        image = image.resize(nw, nh, img_ra);
-        image = apply_image_ops(image, image_ops);
-        image
-    } else {
-        DynamicImage::new(1000, 1000, image::ColorType::Rgb8)
     }
 }
 
+pub fn open_image(img_path: &str) -> Result<DynamicImage, String> {
+    image::open(img_path).map_err(|e| format!("unable to open {img_path}: {e}"))
+}
+
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct ImageOperations {
@@ -1,2 +1,6 @@
+pub mod backend;
+pub mod crop;
 pub mod display;
+pub mod fit;
 pub mod image;
+pub mod provider;
@@ -0,0 +1,510 @@
+use std::env;
+use std::process::{Command, Stdio};
+
+use serde::{Deserialize, Serialize};
+
+use crate::daemon::config::Config;
+use crate::logger::logger::{info, warn};
+use crate::unblock_signals;
+use common::display::{Display, Transform};
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProviderKind {
+    // picked from the environment of the daemon
+    Auto,
+    WlrRandr,
+    Sway,
+    Hyprland,
+    Xrandr,
+}
+
+impl ProviderKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ProviderKind::Auto => "auto",
+            ProviderKind::WlrRandr => "wlr-randr",
+            ProviderKind::Sway => "sway",
+            ProviderKind::Hyprland => "hyprland",
+            ProviderKind::Xrandr => "xrandr",
+        }
+    }
+}
+
+pub trait DisplayProvider {
+    fn command(&self) -> Command;
+    fn parse(&self, output: &str) -> Result<Vec<Display>, String>;
+}
+
+pub fn get_provider(kind: ProviderKind) -> Option<Box<dyn DisplayProvider>> {
+    match kind {
+        ProviderKind::Auto => detect_kind().and_then(get_provider),
+        ProviderKind::WlrRandr => Some(Box::new(WlrRandr)),
+        ProviderKind::Sway => Some(Box::new(Sway)),
+        ProviderKind::Hyprland => Some(Box::new(Hyprland)),
+        ProviderKind::Xrandr => Some(Box::new(Xrandr)),
+    }
+}
+
+pub fn detect_kind() -> Option<ProviderKind> {
+    let set = |name: &str| env::var_os(name).is_some_and(|value| !value.is_empty());
+    if set("SWAYSOCK") {
+        Some(ProviderKind::Sway)
+    } else if set("HYPRLAND_INSTANCE_SIGNATURE") {
+        Some(ProviderKind::Hyprland)
+    } else if set("WAYLAND_DISPLAY") {
+        Some(ProviderKind::WlrRandr)
+    } else if set("DISPLAY") {
+        Some(ProviderKind::Xrandr)
+    } else {
+        None
+    }
+}
+
+// displays are detected when a provider is configured, when the config has none of its own
+// or when profiles are matched against the connected displays
+pub fn wanted_provider(config: &Config) -> Option<ProviderKind> {
+    config.display_provider.or_else(|| {
+        let no_displays = config
+            .configured_displays
+            .as_ref()
+            .map_or(true, Vec::is_empty);
+        let profiles = config.profiles.as_ref().is_some_and(|p| !p.is_empty());
+        (no_displays || profiles).then_some(ProviderKind::Auto)
+    })
+}
+
+// `display_command` replaces the command of the provider, its output is parsed the same way
+pub fn query_displays(kind: ProviderKind, command: Option<&str>) -> Result<Vec<Display>, String> {
+    let provider = get_provider(kind)
+        .ok_or_else(|| "no compositor or X server found in the environment".to_string())?;
+    let mut command = match command {
+        Some(command) => {
+            let mut shell = Command::new("bash");
+            shell.args(["-c", command]);
+            shell
+        }
+        None => provider.command(),
+    };
+
+    let program = command.get_program().to_string_lossy().to_string();
+    let output = unblock_signals(&mut command)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| format!("unable to run {program}: {e}"))?;
+    if !output.status.success() {
+        return Err(format!("{program} exited with {}", output.status));
+    }
+
+    let displays = provider.parse(&String::from_utf8_lossy(&output.stdout))?;
+    if displays.is_empty() {
+        return Err("no active displays reported".to_string());
+    }
+    Ok(displays)
+}
+
+// picks the displays of the config: a profile selected by request, the profile pinned in the
+// config or the one matching the connected displays replaces `displays`, detected displays
+// replace both when a provider is configured or nothing else is, keeping the configured bezels
+pub fn resolve_displays(config: &mut Config, requested_profile: Option<&str>) {
+    let detected = wanted_provider(config).and_then(|kind| {
+        let kind = match kind {
+            ProviderKind::Auto => detect_kind().unwrap_or(kind),
+            kind => kind,
+        };
+        match query_displays(kind, config.display_command.as_deref()) {
+            Ok(displays) => {
+                info(&format!(
+                    "Detected {} displays through {}.",
+                    displays.len(),
+                    kind.name()
+                ));
+                Some(displays)
+            }
+            Err(e) => {
+                warn(&format!(
+                    "Unable to detect displays through {}: {e}, using configured displays.",
+                    kind.name()
+                ));
+                None
+            }
+        }
+    });
+
+    config.active_profile = select_profile(config, requested_profile, detected.as_deref());
+    let configured = match &config.active_profile {
+        Some(name) => config
+            .profiles
+            .as_ref()
+            .and_then(|profiles| profiles.get(name))
+            .map(|profile| profile.displays.clone()),
+        None => config.configured_displays.clone(),
+    };
+
+    config.displays = match detected {
+        Some(mut displays) if config.display_provider.is_some() || configured.is_none() => {
+            for display in &mut displays {
+                if let Some(known) = configured
+                    .iter()
+                    .flatten()
+                    .find(|known| known.name == display.name)
+                {
+                    display.bezel = known.bezel;
+                }
+            }
+            Some(displays)
+        }
+        _ => configured,
+    };
+}
+
+// a profile matches when it has exactly the connected displays, ties go to the first name
+fn select_profile(
+    config: &Config,
+    requested: Option<&str>,
+    detected: Option<&[Display]>,
+) -> Option<String> {
+    let profiles = config.profiles.as_ref()?;
+    if let Some(name) = requested.or(config.profile.as_deref()) {
+        if profiles.contains_key(name) {
+            return Some(name.to_string());
+        }
+        warn(&format!("There is no display profile named {name}."));
+    }
+
+    let mut connected: Vec<&str> = detected?.iter().map(|d| d.name.as_str()).collect();
+    connected.sort_unstable();
+    let mut names: Vec<&String> = profiles.keys().collect();
+    names.sort();
+    names
+        .into_iter()
+        .find(|name| {
+            let mut outputs: Vec<&str> = profiles[*name]
+                .displays
+                .iter()
+                .map(|d| d.name.as_str())
+                .collect();
+            outputs.sort_unstable();
+            outputs == connected
+        })
+        .cloned()
+}
+
+#[derive(Deserialize)]
+struct WlrOutput {
+    name: String,
+    enabled: bool,
+    modes: Vec<WlrMode>,
+    position: Option<WlrPosition>,
+    transform: Option<Transform>,
+    scale: Option<f32>,
+}
+
+#[derive(Deserialize)]
+struct WlrMode {
+    width: u32,
+    height: u32,
+    #[serde(default)]
+    current: bool,
+}
+
+#[derive(Deserialize)]
+struct WlrPosition {
+    x: i32,
+    y: i32,
+}
+
+struct WlrRandr;
+
+impl DisplayProvider for WlrRandr {
+    fn command(&self) -> Command {
+        let mut command = Command::new("wlr-randr");
+        command.arg("--json");
+        command
+    }
+
+    fn parse(&self, output: &str) -> Result<Vec<Display>, String> {
+        let outputs: Vec<WlrOutput> = serde_json::from_str(output)
+            .map_err(|e| format!("unable to parse wlr-randr output: {e}"))?;
+        Ok(outputs
+            .into_iter()
+            .filter(|output| output.enabled)
+            .filter_map(|output| {
+                let mode = output.modes.iter().find(|mode| mode.current)?;
+                let position = output.position.unwrap_or(WlrPosition { x: 0, y: 0 });
+                let mut display =
+                    Display::new(output.name, mode.width, mode.height, position.x, position.y);
+                display.scale = output.scale.unwrap_or(1.0);
+                display.transform = output.transform.unwrap_or_default();
+                Some(display)
+            })
+            .collect())
+    }
+}
+
+#[derive(Deserialize)]
+struct SwayOutput {
+    name: String,
+    #[serde(default)]
+    active: bool,
+    rect: SwayRect,
+    scale: Option<f32>,
+    transform: Option<Transform>,
+    current_mode: Option<SwayMode>,
+}
+
+#[derive(Deserialize)]
+struct SwayRect {
+    x: i32,
+    y: i32,
+}
+
+#[derive(Deserialize)]
+struct SwayMode {
+    width: u32,
+    height: u32,
+}
+
+struct Sway;
+
+impl DisplayProvider for Sway {
+    fn command(&self) -> Command {
+        let mut command = Command::new("swaymsg");
+        command.args(["-t", "get_outputs", "--raw"]);
+        command
+    }
+
+    fn parse(&self, output: &str) -> Result<Vec<Display>, String> {
+        let outputs: Vec<SwayOutput> = serde_json::from_str(output)
+            .map_err(|e| format!("unable to parse swaymsg output: {e}"))?;
+        Ok(outputs
+            .into_iter()
+            .filter(|output| output.active)
+            .filter_map(|output| {
+                let mode = output.current_mode?;
+                let mut display = Display::new(
+                    output.name,
+                    mode.width,
+                    mode.height,
+                    output.rect.x,
+                    output.rect.y,
+                );
+                display.scale = output.scale.unwrap_or(1.0);
+                display.transform = output.transform.unwrap_or_default();
+                Some(display)
+            })
+            .collect())
+    }
+}
+
+#[derive(Deserialize)]
+struct HyprlandMonitor {
+    name: String,
+    width: u32,
+    height: u32,
+    x: i32,
+    y: i32,
+    scale: Option<f32>,
+    // wl_output transform values, 0 to 7
+    #[serde(default)]
+    transform: u8,
+    #[serde(default)]
+    disabled: bool,
+}
+
+struct Hyprland;
+
+impl DisplayProvider for Hyprland {
+    fn command(&self) -> Command {
+        let mut command = Command::new("hyprctl");
+        command.args(["monitors", "-j"]);
+        command
+    }
+
+    fn parse(&self, output: &str) -> Result<Vec<Display>, String> {
+        let monitors: Vec<HyprlandMonitor> = serde_json::from_str(output)
+            .map_err(|e| format!("unable to parse hyprctl output: {e}"))?;
+        monitors
+            .into_iter()
+            .filter(|monitor| !monitor.disabled)
+            .map(|monitor| {
+                let mut display = Display::new(
+                    monitor.name,
+                    monitor.width,
+                    monitor.height,
+                    monitor.x,
+                    monitor.y,
+                );
+                display.scale = monitor.scale.unwrap_or(1.0);
+                display.transform = match monitor.transform {
+                    0 => Transform::Normal,
+                    1 => Transform::Rotate90,
+                    2 => Transform::Rotate180,
+                    3 => Transform::Rotate270,
+                    4 => Transform::Flipped,
+                    5 => Transform::Flipped90,
+                    6 => Transform::Flipped180,
+                    7 => Transform::Flipped270,
+                    other => return Err(format!("unknown hyprland transform {other}")),
+                };
+                Ok(display)
+            })
+            .collect()
+    }
+}
+
+struct Xrandr;
+
+impl DisplayProvider for Xrandr {
+    fn command(&self) -> Command {
+        let mut command = Command::new("xrandr");
+        command.arg("--query");
+        command
+    }
+
+    // `HDMI-1 connected primary 1080x1920+1920+0 left (normal left inverted right ...) ...`
+    fn parse(&self, output: &str) -> Result<Vec<Display>, String> {
+        let mut displays = Vec::new();
+        for line in output.lines() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.get(1) != Some(&"connected") {
+                continue;
+            }
+            // connected outputs without a geometry are turned off
+            let Some(index) = words.iter().position(|word| word.contains('+')) else {
+                continue;
+            };
+            let (w, h, x, y) = parse_geometry(words[index])
+                .ok_or_else(|| format!("unable to parse xrandr geometry `{}`", words[index]))?;
+            let transform = match words.get(index + 1) {
+                Some(&"left") => Transform::Rotate90,
+                Some(&"inverted") => Transform::Rotate180,
+                Some(&"right") => Transform::Rotate270,
+                _ => Transform::Normal,
+            };
+
+            // xrandr reports the rotated size, the display keeps the size of its mode
+            let (w, h) = if transform.swaps_axes() {
+                (h, w)
+            } else {
+                (w, h)
+            };
+            let mut display = Display::new(words[0].to_string(), w, h, x, y);
+            display.transform = transform;
+            displays.push(display);
+        }
+        Ok(displays)
+    }
+}
+
+// WxH+X+Y
+fn parse_geometry(geometry: &str) -> Option<(u32, u32, i32, i32)> {
+    let (size, position) = geometry.split_once('+')?;
+    let (w, h) = size.split_once('x')?;
+    let (x, y) = position.split_once('+')?;
+    Some((
+        w.parse().ok()?,
+        h.parse().ok()?,
+        x.parse().ok()?,
+        y.parse().ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // name, mode, position, scale and transform of every display
+    fn summary(displays: &[Display]) -> Vec<(&str, u32, u32, i32, i32, f32, &'static str)> {
+        displays
+            .iter()
+            .map(|d| {
+                (
+                    d.name.as_str(),
+                    d.w,
+                    d.h,
+                    d.x,
+                    d.y,
+                    d.scale,
+                    d.transform.name(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_sway_outputs() {
+        let displays = Sway
+            .parse(include_str!("../../fixtures/sway-outputs.json"))
+            .unwrap();
+        assert_eq!(
+            summary(&displays),
+            [
+                ("DP-1", 3840, 2160, 0, 0, 1.5, "normal"),
+                ("HDMI-A-1", 1920, 1080, 2560, -240, 1.0, "90"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_hyprland_monitors() {
+        let displays = Hyprland
+            .parse(include_str!("../../fixtures/hyprland-monitors.json"))
+            .unwrap();
+        assert_eq!(
+            summary(&displays),
+            [
+                ("DP-1", 3840, 2160, 0, 0, 1.5, "normal"),
+                ("HDMI-A-1", 1920, 1080, 2560, -240, 1.0, "90"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_wlr_randr_outputs() {
+        let displays = WlrRandr
+            .parse(include_str!("../../fixtures/wlr-randr.json"))
+            .unwrap();
+        assert_eq!(
+            summary(&displays),
+            [
+                ("DP-1", 3840, 2160, 0, 0, 1.5, "normal"),
+                ("HDMI-A-1", 1920, 1080, 2560, -240, 1.0, "90"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_xrandr_query() {
+        let displays = Xrandr
+            .parse(include_str!("../../fixtures/xrandr.txt"))
+            .unwrap();
+        assert_eq!(
+            summary(&displays),
+            [
+                ("DP-1", 1920, 1080, 0, 420, 1.0, "normal"),
+                ("HDMI-1", 1920, 1080, 1920, 0, 1.0, "90"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_output() {
+        for provider in [
+            ProviderKind::Sway,
+            ProviderKind::Hyprland,
+            ProviderKind::WlrRandr,
+        ] {
+            let provider = get_provider(provider).unwrap();
+            assert!(provider.parse("[{\"name\": \"DP-1\"").is_err());
+            assert!(provider.parse("{}").is_err());
+        }
+        assert!(Hyprland
+            .parse(r#"[{"name": "DP-1", "width": 1920, "height": 1080, "x": 0, "y": 0, "transform": 9}]"#)
+            .is_err());
+        assert!(Xrandr
+            .parse("DP-1 connected primary 1920x+0+0 (normal left inverted right)")
+            .is_err());
+    }
+}